@@ -2,30 +2,159 @@
 
 
 use super::std;
-use super::std::rand::{Rand, RngUtil};
+use super::std::rand::{Rand, Rng, RngUtil, IsaacRng};
 
 use std::cell::Cell;
 use std::hashmap::{HashMap, HashSet};
+use extra::treemap::{TreeMap, TreeSet};
+use extra::ringbuf::RingBuf;
+use extra::dlist::DList;
+use extra::priority_queue::PriorityQueue;
+use extra::bitv::{Bitv, from_bools};
+use extra::smallintmap::SmallIntMap;
+
+/* Source */
+
+/**
+ A Source supplies the random choices and the size factor used by Arbitrary.
+
+ A Source is created from a seed, and the same seed always produces the same
+ sequence of values.
+
+ A Source can also record the choices it makes, and a new Source can make the
+ same choices again with `from_choices`, to generate the same value.
+ */
+pub struct Source {
+    priv rng: IsaacRng,
+    priv size: uint,
+    priv boundary: uint,
+    /* the recorded or replayed choices, if any, and the number used */
+    priv choices: Option<~[u32]>,
+    priv used: uint,
+    priv replay: bool,
+}
+
+impl Source {
+    /// Create a new Source from `seed`, with size factor `size`
+    pub fn new(seed: uint, size: uint) -> Source {
+        Source::for_trial(seed, 0, size)
+    }
+
+    /// Create the Source for trial number `trial` of a run using `seed`.
+    /// Each trial can be reproduced on its own from its seed and index.
+    pub fn for_trial(seed: uint, trial: uint, size: uint) -> Source {
+        let bytes = std::vec::from_fn(16, |i| {
+            let x = if i < 8 { seed } else { trial };
+            ((x as u64) >> (8 * (i % 8))) as u8
+        });
+        Source{rng: IsaacRng::new_seeded(bytes), size: size, boundary: 10,
+               choices: None, used: 0, replay: false}
+    }
+
+    /// Create a Source that makes `choices` in order, and then only zero
+    /// choices, with size factor `size`
+    pub fn from_choices(choices: ~[u32], size: uint) -> Source {
+        let mut g = Source::new(0, size);
+        g.choices = Some(choices);
+        g.replay = true;
+        g
+    }
+
+    /// Create a new Source from `seed` with the size factor and boundary
+    /// percentage of this one, which records its choices if this one records
+    /// or replays choices
+    pub fn reseeded(&self, seed: uint) -> Source {
+        let mut g = Source::new(seed, self.size);
+        g.boundary = self.boundary;
+        if self.choices.is_some() {
+            g.record_choices();
+        }
+        g
+    }
+
+    /// Record the choices made from now on, for `choices`
+    pub fn record_choices(&mut self) {
+        if self.choices.is_none() {
+            self.choices = Some(~[]);
+            self.used = 0;
+        }
+    }
+
+    /// Return the choices recorded or replayed so far
+    pub fn choices(&self) -> ~[u32] {
+        match self.choices {
+            Some(ref c) => c.slice(0, self.used).to_owned(),
+            None => ~[],
+        }
+    }
+
+    /// Return the current size factor
+    pub fn size(&self) -> uint {
+        self.size
+    }
+
+    /// Set the size factor
+    pub fn set_size(&mut self, sz: uint) {
+        self.size = sz;
+    }
+
+    /// Set the percentage of numbers that are drawn from the boundary values
+    /// of their type, like 0, -1, MAX and MIN (default 10)
+    pub fn set_boundary(&mut self, percent: uint) {
+        self.boundary = percent;
+    }
+
+    /// Return a random index less than `n`
+    pub fn gen_index(&mut self, n: uint) -> uint {
+        self.gen_uint_range(0, n)
+    }
+
+    /// Call `f` with the size factor temporarily set to `sz`
+    pub fn with_size<T>(&mut self, sz: uint, f: &fn(&mut Source) -> T) -> T {
+        let old = self.size;
+        self.size = sz;
+        let x = f(self);
+        self.size = old;
+        x
+    }
+}
+
+impl Rng for Source {
+    #[inline]
+    fn next(&mut self) -> u32 {
+        match self.choices {
+            None => self.rng.next(),
+            Some(ref mut c) => {
+                if self.used == c.len() {
+                    c.push(if self.replay { 0 } else { self.rng.next() });
+                }
+                self.used += 1;
+                c[self.used - 1]
+            }
+        }
+    }
+}
+
 /* Arbitrary */
 
 /**
  The Arbitrary trait can generate a randomly chosen value (with restrictions).
- You can pass a size factor to allow specifying test size (sizes of vectors and
- numbers).
+ The Source passed in supplies both the random choices and a size factor to
+ allow specifying test size (sizes of vectors and numbers).
  */
 pub trait Arbitrary {
     /**
      arbitrary should return an arbitrary value of its type.
-     The value should be randomly chosen and its size should be scaled by the size
-     parameter.
+     The value should be chosen using the Source's random choices and its size
+     should be scaled by the Source's size factor.
      */
-    fn arbitrary(uint) -> Self;
+    fn arbitrary(&mut Source) -> Self;
 }
 
 /// Create an arbitrary value of type T
 #[inline]
-pub fn arbitrary<T: Arbitrary>(sz: uint) -> T {
-    Arbitrary::arbitrary(sz)
+pub fn arbitrary<T: Arbitrary>(g: &mut Source) -> T {
+    Arbitrary::arbitrary(g)
 }
 
 /// A wrapper type to reuse an existing Rand instance for the Arbitrary impl
@@ -36,28 +165,28 @@ pub struct Random<T>(T);
 #[deriving(Eq, Clone)]
 pub struct SmallN(uint);
 
-fn small_n(size: uint) -> uint {
-    let f: std::rand::distributions::Exp1 = std::rand::random();
+fn small_n(g: &mut Source) -> uint {
+    let size = g.size();
+    let f: std::rand::distributions::Exp1 = g.gen();
     let n = ((*f) * (size as f64)) as uint;
     n.min(&(16 * size))
 }
 
 /* Helper: Iter */
-#[deriving(Clone)]
-struct Iter<T> {
+struct Iter<'self, T> {
+    g: &'self mut Source,
     count: uint,
-    size: uint,
 }
 
-fn arbiter<T>(sz: uint) -> Iter<T> {
-    Iter{count: small_n(sz), size: sz }
+fn arbiter<'a, T>(g: &'a mut Source) -> Iter<'a, T> {
+    Iter{count: small_n(g), g: g}
 }
 
-impl<T: Arbitrary> Iterator<T> for Iter<T> {
+impl<'self, T: Arbitrary> Iterator<T> for Iter<'self, T> {
     fn next(&mut self) -> Option<T> {
         if self.count > 0 {
             self.count -= 1;
-            Some(arbitrary(self.size))
+            Some(arbitrary(&mut *self.g))
         } else { None }
     }
 
@@ -67,89 +196,289 @@ impl<T: Arbitrary> Iterator<T> for Iter<T> {
 }
 
 
+/// The number of boundary values of the integer type T, for `boundary_value`
+fn boundary_count<T: Int + Bounded>() -> uint {
+    use std::num::Zero;
+    let bits = std::sys::size_of::<T>() * 8;
+    let min: T = Bounded::min_value();
+    /* 2^k up to the largest power of two of the type */
+    if min < Zero::zero() { 7 + 3 * (bits - 2) } else { 6 + 2 * (bits - 1) }
+}
+
+/// Value `k` of the values of the integer type T that are likely to reveal
+/// edge cases: 0, 1, MIN, MAX, their neighbours and -1 (in that order), then
+/// powers of two and their neighbours.
+/// Each is computed on its own, without building the whole list.
+fn boundary_value<T: Int + Bounded + NumCast + Clone>(k: uint) -> T {
+    use std::num::{Zero, One};
+    let zero: T = Zero::zero();
+    let one: T = One::one();
+    let min: T = Bounded::min_value();
+    let max: T = Bounded::max_value();
+    let signed = min < zero;
+    let base = if signed { 7 } else { 6 };
+    if k < base {
+        return match k {
+            0 => zero,
+            1 => one,
+            2 => min,
+            3 => max,
+            4 => min + one,
+            5 => max - one,
+            _ => zero - one,
+        };
+    }
+    let per = if signed { 3 } else { 2 };
+    let mut p = one + one;
+    for _ in range(0, (k - base) / per) {
+        p = p + p;
+    }
+    match (k - base) % per {
+        0 => p,
+        1 => p - one,
+        _ => zero - p,
+    }
+}
+
+/// An integer with magnitude scaled by the size factor, like `small_n`,
+/// or sometimes one of the boundary values of its type
+fn arbitrary_int<T: Int + Bounded + NumCast + Clone>(g: &mut Source) -> T {
+    use std::num::Zero;
+    if g.gen_index(100) < g.boundary {
+        /* pick from 0, 1, MIN, MAX and neighbours half of the time */
+        let n = boundary_count::<T>();
+        let k = if g.gen() { g.gen_index(7) } else { g.gen_index(n) };
+        return boundary_value(k);
+    }
+    /* NumCast wraps values out of range */
+    let zero: T = Zero::zero();
+    let n: T = NumCast::from(small_n(g));
+    let min: T = Bounded::min_value();
+    if min < zero && g.gen() {
+        zero - n
+    } else {
+        n
+    }
+}
+
+macro_rules! arb_int( ($T:ty) => (
+        impl Arbitrary for $T {
+            fn arbitrary(g: &mut Source) -> $T {
+                arbitrary_int(g)
+            }
+        }
+    )
+)
+
 macro_rules! arb_rand( ($T:ty) => (
         impl Arbitrary for $T {
-            fn arbitrary(_: uint) -> $T {
-                std::rand::random()
+            fn arbitrary(g: &mut Source) -> $T {
+                g.gen()
             }
         }
     )
 )
 
-macro_rules! arb_tuple( ($($T:ident),+ ) => (
-        impl<$($T: Arbitrary),+> Arbitrary for ($($T),+) {
-            fn arbitrary(sz: uint) -> ($($T),+) {
-                ($(arbitrary::<$T>(sz)),+)
+/* The indices only count the elements */
+macro_rules! arb_array( ($N:expr, $($i:expr),+) => (
+        impl<T: Arbitrary> Arbitrary for [T, ..$N] {
+            fn arbitrary(g: &mut Source) -> [T, ..$N] {
+                [$({ let _i = $i; arbitrary::<T>(g) }),+]
             }
         }
     )
 )
 
-arb_rand!(i8)
-//arb_rand!(u8)
-arb_rand!(int)
-arb_rand!(uint)
-arb_rand!(float)
+arb_int!(i8)
+arb_int!(i16)
+arb_int!(i32)
+arb_int!(i64)
+arb_int!(int)
+arb_int!(u8)
+arb_int!(u16)
+arb_int!(u32)
+arb_int!(u64)
+arb_int!(uint)
 arb_rand!(bool)
 arb_rand!(())
 
-arb_tuple!(A, B)
-arb_tuple!(A, B, C)
-arb_tuple!(A, B, C, D)
-arb_tuple!(A, B, C, D, E)
-arb_tuple!(A, B, C, D, E, F)
-arb_tuple!(A, B, C, D, E, F, G)
-arb_tuple!(A, B, C, D, E, F, G, H)
+arb_array!(1, 0)
+arb_array!(2, 0, 1)
+arb_array!(3, 0, 1, 2)
+arb_array!(4, 0, 1, 2, 3)
+arb_array!(5, 0, 1, 2, 3, 4)
+arb_array!(6, 0, 1, 2, 3, 4, 5)
+arb_array!(7, 0, 1, 2, 3, 4, 5, 6)
+arb_array!(8, 0, 1, 2, 3, 4, 5, 6, 7)
+arb_array!(16, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15)
+arb_array!(32, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+           16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31)
+
+/**
+ A float that is either one of `specials`, a number with magnitude scaled by
+ the size factor, or a number with an exponent from the whole range of the
+ type, generated by `wide`.
+ */
+fn arbitrary_float<T: Float + NumCast + Clone>(g: &mut Source, specials: &[T],
+                                               wide: &fn(&mut Source) -> T) -> T {
+    if g.gen_index(100) < g.boundary {
+        return specials[g.gen_index(specials.len())].clone();
+    }
+    if g.gen() {
+        let mut n = small_n(g) as f64;
+        if g.gen() {
+            n += g.gen::<f64>();
+        }
+        let x: T = NumCast::from(n);
+        if g.gen() { -x } else { x }
+    } else {
+        wide(g)
+    }
+}
+
+fn f64_from_bits(x: u64) -> f64 { unsafe { std::cast::transmute(x) } }
+fn f32_from_bits(x: u32) -> f32 { unsafe { std::cast::transmute(x) } }
+
+impl Arbitrary for f64 {
+    fn arbitrary(g: &mut Source) -> f64 {
+        let specials = [
+            0.0, f64_from_bits(0x8000000000000000u64), 1.0, -1.0,
+            f64_from_bits(0x7ff8000000000000u64),   /* NaN */
+            f64_from_bits(0x7ff0000000000000u64),   /* inf */
+            f64_from_bits(0xfff0000000000000u64),   /* -inf */
+            f64_from_bits(0x7fefffffffffffffu64),   /* max */
+            f64_from_bits(0xffefffffffffffffu64),   /* -max */
+            f64_from_bits(0x0010000000000000u64),   /* min normal */
+            f64_from_bits(0x000fffffffffffffu64),   /* max subnormal */
+            f64_from_bits(0x0000000000000001u64),   /* min subnormal */
+            f64_from_bits(0x8000000000000001u64),   /* -min subnormal */
+            f64_from_bits(0x3cb0000000000000u64),   /* epsilon */
+        ];
+        do arbitrary_float(g, specials) |g| {
+            /* any sign and exponent except inf and NaN; exponent 0 is subnormal */
+            let sign = g.gen::<u64>() & (1 << 63);
+            let exp = g.gen_index(0x7ff) as u64;
+            f64_from_bits(sign | (exp << 52) | (g.gen::<u64>() & ((1 << 52) - 1)))
+        }
+    }
+}
+
+impl Arbitrary for f32 {
+    fn arbitrary(g: &mut Source) -> f32 {
+        let specials = [
+            0.0, f32_from_bits(0x80000000u32), 1.0, -1.0,
+            f32_from_bits(0x7fc00000u32),   /* NaN */
+            f32_from_bits(0x7f800000u32),   /* inf */
+            f32_from_bits(0xff800000u32),   /* -inf */
+            f32_from_bits(0x7f7fffffu32),   /* max */
+            f32_from_bits(0xff7fffffu32),   /* -max */
+            f32_from_bits(0x00800000u32),   /* min normal */
+            f32_from_bits(0x007fffffu32),   /* max subnormal */
+            f32_from_bits(0x00000001u32),   /* min subnormal */
+            f32_from_bits(0x80000001u32),   /* -min subnormal */
+            f32_from_bits(0x34000000u32),   /* epsilon */
+        ];
+        do arbitrary_float(g, specials) |g| {
+            let sign = g.gen::<u32>() & (1 << 31);
+            let exp = g.gen_index(0xff) as u32;
+            f32_from_bits(sign | (exp << 23) | (g.gen::<u32>() & ((1 << 23) - 1)))
+        }
+    }
+}
+
+impl Arbitrary for float {
+    fn arbitrary(g: &mut Source) -> float {
+        arbitrary::<f64>(g) as float
+    }
+}
 
 impl<T: Rand> Arbitrary for Random<T> {
-    fn arbitrary(_: uint) -> Random<T> {
-        Random(std::rand::random())
+    fn arbitrary(g: &mut Source) -> Random<T> {
+        Random(g.gen())
     }
 }
 
 impl<T: Arbitrary> Arbitrary for ~T {
     #[inline]
-    fn arbitrary(sz: uint) -> ~T { ~arbitrary(sz) }
+    fn arbitrary(g: &mut Source) -> ~T { ~arbitrary(g) }
 }
 
 impl<T: 'static + Arbitrary> Arbitrary for @T {
     #[inline]
-    fn arbitrary(sz: uint) -> @T { @arbitrary(sz) }
+    fn arbitrary(g: &mut Source) -> @T { @arbitrary(g) }
 }
 
 impl<T: 'static + Arbitrary> Arbitrary for @mut T {
     #[inline]
-    fn arbitrary(sz: uint) -> @mut T { @mut arbitrary(sz) }
+    fn arbitrary(g: &mut Source) -> @mut T { @mut arbitrary(g) }
 }
 
-impl Arbitrary for u8 {
-    fn arbitrary(_: uint) -> u8 {
-        std::rand::random()
-    }
+/// Code points that tend to reveal bugs in text handling
+static SPECIAL_CHARS: [char, ..24] = [
+    '\x00', '\t', '\n', '\r', ' ', '\x7f',
+    '\xe9',        /* e with acute accent, 2 bytes in UTF-8 */
+    '\u0301',      /* combining acute accent */
+    '\u0308',      /* combining diaeresis */
+    '\u200b',      /* zero width space */
+    '\u200c',      /* zero width non-joiner */
+    '\u200d',      /* zero width joiner */
+    '\ufeff',      /* zero width no-break space (BOM) */
+    '\u200f',      /* right-to-left mark */
+    '\u202e',      /* right-to-left override */
+    '\u05d0',      /* hebrew letter alef */
+    '\u0627',      /* arabic letter alef */
+    '\u4e2d',      /* CJK ideograph, 3 bytes in UTF-8 */
+    '\ud7ff',      /* last code point before the surrogates */
+    '\ue000',      /* first code point after the surrogates */
+    '\ufffd',      /* replacement character */
+    '\uffff',      /* last code point in the BMP */
+    '\U0001f600',  /* emoji, 4 bytes in UTF-8 */
+    '\U0010ffff',  /* last code point */
+];
+
+/// The code point at index `n` when the surrogates 0xD800 to 0xDFFF are skipped
+fn skip_surrogates(n: u32) -> char {
+    (if n >= 0xd800 { n + 0x800 } else { n }) as char
 }
 
+/**
+ Generate chars from the whole range of Unicode scalar values, weighted toward
+ ASCII, with some multi-byte, combining, right-to-left, zero-width and
+ surrogate-adjacent code points.
+ */
 impl Arbitrary for char {
-    fn arbitrary(_: uint) -> char {
-        std::rand::random::<u8>() as char
+    fn arbitrary(g: &mut Source) -> char {
+        match g.gen_index(20) {
+            /* printable ASCII */
+            0 .. 9 => g.gen_uint_range(0x20, 0x7f) as u8 as char,
+            /* any ASCII */
+            10 .. 11 => g.gen_uint_range(0, 0x80) as u8 as char,
+            12 .. 14 => SPECIAL_CHARS[g.gen_index(SPECIAL_CHARS.len())],
+            /* two bytes in UTF-8 */
+            15 .. 16 => g.gen_uint_range(0x80, 0x800) as u32 as char,
+            /* the rest of the Basic Multilingual Plane */
+            17 .. 18 => skip_surrogates(g.gen_uint_range(0x800, 0x10000 - 0x800) as u32),
+            _ => skip_surrogates(g.gen_uint_range(0, 0x110000 - 0x800) as u32),
+        }
     }
 }
 
 impl Arbitrary for SmallN {
-    fn arbitrary(sz: uint) -> SmallN {
-        SmallN(small_n(sz))
+    fn arbitrary(g: &mut Source) -> SmallN {
+        SmallN(small_n(g))
     }
 }
 
 impl<T: Arbitrary> Arbitrary for ~[T] {
-    fn arbitrary(sz: uint) -> ~[T] {
-        arbiter::<T>(sz).collect()
+    fn arbitrary(g: &mut Source) -> ~[T] {
+        arbiter::<T>(g).collect()
     }
 }
 
 impl<T: Arbitrary> Arbitrary for Option<T> {
-    fn arbitrary(sz: uint) -> Option<T> {
-        if std::rand::random() {
-            Some(arbitrary(sz))
+    fn arbitrary(g: &mut Source) -> Option<T> {
+        if g.gen() {
+            Some(arbitrary(g))
         } else {
             None
         }
@@ -158,37 +487,36 @@ impl<T: Arbitrary> Arbitrary for Option<T> {
 }
 
 impl<T: Arbitrary, U: Arbitrary> Arbitrary for Result<T, U> {
-    fn arbitrary(sz: uint) -> Result<T, U> {
-        if std::rand::random() {
-            Ok(arbitrary(sz))
+    fn arbitrary(g: &mut Source) -> Result<T, U> {
+        if g.gen() {
+            Ok(arbitrary(g))
         } else {
-            Err(arbitrary(sz))
+            Err(arbitrary(g))
         }
     }
 }
 
 impl<T: Arbitrary, U: Arbitrary> Arbitrary for Either<T, U> {
-    fn arbitrary(sz: uint) -> Either<T, U> {
-        if std::rand::random() {
-            Left(arbitrary(sz))
+    fn arbitrary(g: &mut Source) -> Either<T, U> {
+        if g.gen() {
+            Left(arbitrary(g))
         } else {
-            Right(arbitrary(sz))
+            Right(arbitrary(g))
         }
     }
 }
 
 impl Arbitrary for ~str {
-    fn arbitrary(sz: uint) -> ~str {
-        let rng = &mut *std::rand::task_rng();
-        let n = small_n(sz);
-        rng.gen_str(n)
+    fn arbitrary(g: &mut Source) -> ~str {
+        let v: ~[char] = arbitrary(g);
+        std::str::from_chars(v)
     }
 }
 
 impl <T: Arbitrary> Arbitrary for Cell<T> {
-    fn arbitrary(sz: uint) -> Cell<T> {
-        if std::rand::random() {
-            Cell::new(arbitrary(sz))
+    fn arbitrary(g: &mut Source) -> Cell<T> {
+        if g.gen() {
+            Cell::new(arbitrary(g))
         } else {
             Cell::new_empty()
         }
@@ -196,13 +524,61 @@ impl <T: Arbitrary> Arbitrary for Cell<T> {
 }
 
 impl<K: Eq + Hash + Arbitrary> Arbitrary for HashSet<K> {
-    fn arbitrary(sz: uint) -> HashSet<K> {
-        arbiter::<K>(sz).collect()
+    fn arbitrary(g: &mut Source) -> HashSet<K> {
+        arbiter::<K>(g).collect()
     }
 }
 
 impl<K: Eq + Hash + Arbitrary, V: Arbitrary> Arbitrary for HashMap<K, V> {
-    fn arbitrary(sz: uint) -> HashMap<K, V> {
-        arbiter::<(K, V)>(sz).collect()
+    fn arbitrary(g: &mut Source) -> HashMap<K, V> {
+        arbiter::<(K, V)>(g).collect()
+    }
+}
+
+impl<K: TotalOrd + Arbitrary, V: Arbitrary> Arbitrary for TreeMap<K, V> {
+    fn arbitrary(g: &mut Source) -> TreeMap<K, V> {
+        arbiter::<(K, V)>(g).collect()
+    }
+}
+
+impl<T: TotalOrd + Arbitrary> Arbitrary for TreeSet<T> {
+    fn arbitrary(g: &mut Source) -> TreeSet<T> {
+        arbiter::<T>(g).collect()
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for RingBuf<T> {
+    fn arbitrary(g: &mut Source) -> RingBuf<T> {
+        arbiter::<T>(g).collect()
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for DList<T> {
+    fn arbitrary(g: &mut Source) -> DList<T> {
+        arbiter::<T>(g).collect()
+    }
+}
+
+impl<T: Ord + Arbitrary> Arbitrary for PriorityQueue<T> {
+    fn arbitrary(g: &mut Source) -> PriorityQueue<T> {
+        PriorityQueue::from_vec(arbitrary(g))
+    }
+}
+
+impl Arbitrary for Bitv {
+    fn arbitrary(g: &mut Source) -> Bitv {
+        let v: ~[bool] = arbitrary(g);
+        from_bools(v)
+    }
+}
+
+/// Keys are small, since the map is stored as a vector indexed by key
+impl<V: Arbitrary> Arbitrary for SmallIntMap<V> {
+    fn arbitrary(g: &mut Source) -> SmallIntMap<V> {
+        let mut m = SmallIntMap::new();
+        for (k, v) in arbiter::<(SmallN, V)>(g) {
+            m.insert(*k, v);
+        }
+        m
     }
 }
@@ -0,0 +1,123 @@
+// vim: sts=4 sw=4 et
+
+/*!
+ Shrinking through choices: a counterexample is shrunk by shrinking the
+ sequence of random choices its generator made, and generating again from the
+ smaller sequence with `Source::from_choices`.
+
+ Any generator can be shrunk this way, without a `Shrink` impl, and a shrunk
+ value is always one the generator could have produced.
+
+ A sequence is smaller than another if it is shorter, or as long and
+ lexicographically smaller. Every candidate is smaller than the sequence it
+ was made from, so shrinking always finishes.
+ */
+
+use lazy::Lazy;
+
+/// The sizes of the blocks of choices to delete, zero and sort
+static BLOCKS: [uint, ..4] = [8, 4, 2, 1];
+
+static DELETE: uint = 0;
+static ZERO: uint = 1;
+static SORT: uint = 2;
+static MINIMISE: uint = 3;
+
+/**
+ Shrink a sequence of choices, in passes: delete blocks of choices, set blocks
+ to zero, sort blocks, and then make each choice smaller.
+ */
+pub fn shrink_choices(c: &~[u32]) -> Lazy<~[u32]> {
+    let mut L = Lazy::new();
+    push_candidates(&mut L, c.clone(), DELETE, 0, 0);
+    L
+}
+
+/// Lazily push the candidates of `pass` for the block at `i`, and then the rest
+fn push_candidates(L: &mut Lazy<~[u32]>, c: ~[u32], pass: uint, block: uint, i: uint) {
+    do L.push_thunk((c, pass, block, i)) |L, (c, pass, block, i)| {
+        let n = if pass == MINIMISE { 1 } else { BLOCKS[block] };
+        if i + n > c.len() {
+            /* done with this block size */
+            if pass != MINIMISE && block + 1 < BLOCKS.len() {
+                push_candidates(L, c, pass, block + 1, 0);
+            } else if pass != MINIMISE {
+                push_candidates(L, c, pass + 1, 0, 0);
+            }
+        } else {
+            match pass {
+                DELETE => {
+                    let mut d = c.slice(0, i).to_owned();
+                    d.push_all(c.slice(i + n, c.len()));
+                    L.push(d);
+                }
+                ZERO => {
+                    if c.slice(i, i + n).iter().any(|&x| x != 0) {
+                        let mut d = c.clone();
+                        for j in range(i, i + n) {
+                            d[j] = 0;
+                        }
+                        L.push(d);
+                    }
+                }
+                SORT => {
+                    let mut d = c.clone();
+                    for j in range(i + 1, i + n) {
+                        let mut k = j;
+                        while k > i && d[k - 1] > d[k] {
+                            d.swap(k - 1, k);
+                            k -= 1;
+                        }
+                    }
+                    if d != c {
+                        L.push(d);
+                    }
+                }
+                _ => {
+                    /* zero was tried by the previous pass; approach it by halves */
+                    let x = c[i];
+                    let mut step = x / 2;
+                    while step > 0 {
+                        let mut d = c.clone();
+                        d[i] = x - step;
+                        L.push(d);
+                        step /= 2;
+                    }
+                }
+            }
+            push_candidates(L, c, pass, block, i + 1);
+        }
+    }
+}
+
+#[test]
+fn test_choices() {
+    use arbitrary::{Source, arbitrary};
+    use std::rand::Rng;
+
+    let c = ~[5u32, 0, 3, 9, 1, 0, 7, 2, 8, 4, 6];
+    let mut n = 0;
+    for d in shrink_choices(&c) {
+        assert!(d.len() < c.len() || (d.len() == c.len() && d < c));
+        n += 1;
+    }
+    assert!(n > 0);
+    assert_eq!(shrink_choices(&~[]).next(), None);
+    assert_eq!(shrink_choices(&~[0u32]).collect::<~[~[u32]]>(), ~[~[]]);
+    assert_eq!(shrink_choices(&~[2u32, 1]).collect::<~[~[u32]]>(),
+               ~[~[], ~[1], ~[2], ~[0, 0], ~[0, 1], ~[2, 0], ~[1, 2], ~[1, 1]]);
+
+    /* The same choices generate the same value */
+    let mut g = Source::new(7, 8);
+    g.record_choices();
+    let v: ~[int] = arbitrary(&mut g);
+    let mut h = Source::from_choices(g.choices(), 8);
+    assert_eq!(arbitrary::<~[int]>(&mut h), v);
+    assert_eq!(h.choices(), g.choices());
+
+    /* Past the end, every choice is zero */
+    let mut h = Source::from_choices(~[3], 8);
+    assert_eq!(h.next(), 3);
+    assert_eq!(h.next(), 0);
+    assert_eq!(h.choices(), ~[3, 0]);
+}
@@ -0,0 +1,272 @@
+// vim: sts=4 sw=4 et
+
+/*!
+ Gen is a generator value, an alternative to writing a new type with an
+ Arbitrary impl for each custom distribution.
+
+ Generators are built from the functions in this module and combined with
+ the `Gen` methods, and are used with `for_all`.
+
+ A generator builds the shrinks of each value along with it, as a `Rose`
+ tree, so a counterexample found by `for_all` shrinks only to values the
+ generator could have produced.
+
+ Example::
+
+    let small_evens = gen::choose(0, 50).map(|x: int| 2 * x);
+    for_all!(small_evens, |x: int| x % 2 == 0);
+
+ */
+
+use super::std;
+use super::std::rand::RngUtil;
+use super::std::num::Int;
+
+use arbitrary::{Arbitrary, Source, SmallN, arbitrary};
+use shrink::Shrink;
+use lazy::Lazy;
+use rose::Rose;
+use regex::Regex;
+use grammar::Grammar;
+
+/// Number of tries before `such_that` gives up
+static SUCH_THAT_TRIES: uint = 100;
+
+/// A generator of values of type T
+pub struct Gen<T> {
+    priv f: @fn(&mut Source) -> Rose<T>,
+}
+
+impl<T> Clone for Gen<T> {
+    fn clone(&self) -> Gen<T> {
+        Gen{f: self.f}
+    }
+}
+
+impl<T: 'static> Gen<T> {
+    /// Create a generator from a function. Its values do not shrink.
+    pub fn new(f: @fn(&mut Source) -> T) -> Gen<T> {
+        do Gen::from_rose |g| { Rose::leaf(f(g)) }
+    }
+
+    /// Create a generator from a function generating values with their shrinks
+    pub fn from_rose(f: @fn(&mut Source) -> Rose<T>) -> Gen<T> {
+        Gen{f: f}
+    }
+
+    /// Generate a value using the choices and size factor of `g`
+    pub fn generate(&self, g: &mut Source) -> T {
+        let r = (self.f)(g);
+        r.value
+    }
+
+    /// Generate a value and the tree of its shrinks
+    pub fn generate_rose(&self, g: &mut Source) -> Rose<T> {
+        (self.f)(g)
+    }
+
+    /// Generate values of type U by applying `f` to generated values.
+    /// They shrink by shrinking the original value.
+    pub fn map<U: 'static>(&self, f: @fn(T) -> U) -> Gen<U> {
+        let s = self.f;
+        do Gen::from_rose |g| { s(g).map(f) }
+    }
+
+    /**
+     Generate only values for which `p` returns true, by generating new values
+     until one is accepted.
+
+     Fails if no value is accepted after 100 tries.
+     Shrinks for which `p` is false are skipped.
+     */
+    pub fn such_that(&self, p: @fn(&T) -> bool) -> Gen<T> {
+        let s = self.f;
+        do Gen::from_rose |g| {
+            let mut tries = 0;
+            loop {
+                let r = s(g);
+                if p(&r.value) {
+                    return r.filter(p);
+                }
+                tries += 1;
+                if tries >= SUCH_THAT_TRIES {
+                    fail!(fmt!("such_that: no value accepted after %u tries", tries));
+                }
+            }
+        }
+    }
+
+    /**
+     Use each generated value to choose the generator for the result.
+
+     The result shrinks by shrinking the first value and generating again
+     with the same choices, then by shrinking the result itself.
+     */
+    pub fn bind<U: 'static>(&self, f: @fn(T) -> Gen<U>) -> Gen<U> {
+        let s = self.f;
+        do Gen::from_rose |g| {
+            /* generate the result from its own Source, so that the same
+               choices can be made again for each shrink of the first value;
+               it is reseeded from `g` to keep the settings of `g` */
+            let seed: uint = g.gen();
+            let h = @g.reseeded(seed);
+            do s(g).bind |x| { f(x).generate_rose(&mut h.reseeded(seed)) }
+        }
+    }
+
+    /// Generate values using size factor `sz`
+    pub fn resize(&self, sz: uint) -> Gen<T> {
+        let s = self.f;
+        do Gen::from_rose |g| { g.with_size(sz, |g| s(g)) }
+    }
+}
+
+/// Generate values using their Arbitrary impl, shrinking them with Shrink
+pub fn any<T: 'static + Arbitrary + Shrink + Clone>() -> Gen<T> {
+    do Gen::from_rose |g| { Rose::unfold(arbitrary(g), |x| x.shrink()) }
+}
+
+/// Always generate `x`
+pub fn constant<T: 'static + Clone>(x: T) -> Gen<T> {
+    do Gen::new |_| { x.clone() }
+}
+
+/// Shrink integers toward `lo`, halving the distance
+fn towards<T: 'static + Int + NumCast + Clone>(lo: T) -> @fn(&T) -> Lazy<T> {
+    |x| {
+        /* wrapping arithmetic makes this correct for all integer types */
+        let d = (x.to_i64() - lo.to_i64()) as u64;
+        let mut v = ~[];
+        let mut step = d;
+        while step > 0 {
+            v.push(lo + NumCast::from(d - step));
+            step /= 2;
+        }
+        Lazy::new_from(v)
+    }
+}
+
+/// Generate integers uniformly in the range `lo` to `hi`, inclusive.
+/// They shrink toward `lo`.
+pub fn choose<T: 'static + Int + NumCast + Clone>(lo: T, hi: T) -> Gen<T> {
+    assert!(lo <= hi);
+    do Gen::from_rose |g| {
+        let span = (hi.to_i64() - lo.to_i64()) as u64;
+        let mut r = g.gen::<u64>();
+        if span < std::u64::max_value {
+            /* reject the last 2^64 % n draws, which would favour small offsets */
+            let n = span + 1;
+            let rem = (std::u64::max_value % n + 1) % n;
+            while r > std::u64::max_value - rem {
+                r = g.gen::<u64>();
+            }
+            r %= n;
+        }
+        Rose::unfold(lo + NumCast::from(r), towards(lo))
+    }
+}
+
+/// Choose one of the elements of `xs`. They shrink toward the first element.
+pub fn elements<T: 'static + Clone>(xs: &[T]) -> Gen<T> {
+    assert!(xs.len() > 0);
+    let xs = @xs.to_owned();
+    do Gen::from_rose |g| {
+        let i = g.gen_uint_range(0, xs.len());
+        Rose::unfold(i, towards(0u)).map(|i| xs[i].clone())
+    }
+}
+
+/// Use one of the generators `gens`, chosen at random
+pub fn one_of<T: 'static>(gens: ~[Gen<T>]) -> Gen<T> {
+    assert!(gens.len() > 0);
+    do Gen::from_rose |g| { gens[g.gen_uint_range(0, gens.len())].generate_rose(g) }
+}
+
+/// Use one of the generators `gens`, chosen with probability proportional
+/// to its weight
+pub fn frequency<T: 'static>(gens: ~[(uint, Gen<T>)]) -> Gen<T> {
+    let total = gens.iter().fold(0u, |a, &(w, _)| a + w);
+    assert!(total > 0);
+    do Gen::from_rose |g| {
+        let mut k = g.gen_uint_range(0, total);
+        let mut result = None;
+        for &(w, ref gen) in gens.iter() {
+            if k < w {
+                result = Some(gen.generate_rose(g));
+                break;
+            }
+            k -= w;
+        }
+        result.unwrap()
+    }
+}
+
+/// Generate vectors of values from `gen`, their length scaled by the size factor.
+/// They shrink like `~[T]`, and elements shrink as `gen` shrinks them.
+pub fn vec_of<T: 'static + Clone>(gen: Gen<T>) -> Gen<~[T]> {
+    do Gen::from_rose |g| {
+        let n: SmallN = Arbitrary::arbitrary(g);
+        Rose::collect(std::vec::from_fn(*n, |_| gen.generate_rose(g)))
+    }
+}
+
+/// Generate strings matching the regular expression `pattern`, see `Regex`
+pub fn regex(pattern: &str) -> Gen<~str> {
+    let re = @Regex::new(pattern);
+    do Gen::from_rose |g| { Rose::unfold(re.generate(g), |s| re.shrink(s)) }
+}
+
+/// Generate the text of derivations of `grammar`, see `Grammar`.
+/// They shrink on the derivation tree.
+pub fn grammar(grammar: &Grammar) -> Gen<~str> {
+    let gr = @grammar.clone();
+    do Gen::from_rose |g| {
+        Rose::unfold(gr.generate(g), |d| gr.shrink(d)).map(|d| d.to_str())
+    }
+}
+
+/// Use the size factor to choose a generator
+pub fn sized<T: 'static>(f: @fn(uint) -> Gen<T>) -> Gen<T> {
+    do Gen::from_rose |g| { f(g.size()).generate_rose(g) }
+}
+
+#[test]
+fn test_gen_combinators() {
+    let mut g = Source::new(0, 8);
+
+    let c = choose(-3, 3);
+    let d = choose(0u8, 255);
+    for _ in range(0, 100) {
+        let x: int = c.generate(&mut g);
+        assert!(x >= -3 && x <= 3);
+        d.generate(&mut g);
+    }
+
+    let e = elements(&[1, 2, 3]).map(|x| x * 10);
+    let f = one_of(~[constant(1), constant(2)]);
+    let h = frequency(~[(1, constant(true)), (0, constant(false))]);
+    let k = choose(0u, 10).such_that(|x| *x % 2 == 0);
+    let v = vec_of(choose(0u, 1)).resize(4);
+    let n = sized(|sz| constant(sz));
+    let b = choose(1u, 5).bind(|x| vec_of(constant(x)));
+    for _ in range(0, 100) {
+        let x = e.generate(&mut g);
+        assert!(x == 10 || x == 20 || x == 30);
+        let x = f.generate(&mut g);
+        assert!(x == 1 || x == 2);
+        assert!(h.generate(&mut g));
+        assert_eq!(k.generate(&mut g) % 2, 0);
+        assert!(v.generate(&mut g).iter().all(|&x| x <= 1));
+        assert_eq!(n.generate(&mut g), 8);
+        let w = b.generate(&mut g);
+        assert!(w.iter().all(|x| *x == w[0]));
+    }
+
+    /* the Source of the result of bind keeps the settings of the caller's */
+    let mut g = Source::new(0, 1);
+    g.set_boundary(0);
+    let b = choose(0u, 1).bind(|_| any::<uint>());
+    for _ in range(0, 100) {
+        assert!(b.generate(&mut g) <= 16);
+    }
+}
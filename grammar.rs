@@ -0,0 +1,427 @@
+// vim: sts=4 sw=4 et
+
+/*!
+ Grammar generates derivations of a small context-free grammar, and shrinks
+ them on the derivation tree instead of on the text.
+
+ A production is a list of symbols separated by spaces: `'text'` is literal
+ text, `/pattern/` is text matching a `Regex`, and a bare name refers to a rule.
+ Adding several productions to the same rule makes them alternatives, chosen
+ with probability proportional to their weight.
+
+ The size factor is the depth budget of a derivation. Once it is used up, only
+ the alternatives that finish soonest are chosen.
+
+ Example::
+
+    let expr = Grammar::new("expr")
+        .rule("expr", 3, "num")
+        .rule("expr", 1, "expr '+' expr")
+        .rule("expr", 1, "'(' expr ')'")
+        .rule("num", 1, "/[1-9][0-9]*/");
+    for_all!(gen::grammar(&expr), |s: ~str| parse(s).is_ok());
+
+ */
+
+use super::std;
+use super::std::rand::RngUtil;
+
+use lazy::Lazy;
+use arbitrary::Source;
+use regex::Regex;
+use extra::arc::Arc;
+
+/// A context-free grammar
+#[deriving(Clone)]
+pub struct Grammar {
+    /* shared with the shrinks of derivations instead of copied into them */
+    priv rules: Arc<Rules>,
+}
+
+/* The rules of a grammar, and what `Grammar::rule` works out from them */
+#[deriving(Clone)]
+struct Rules {
+    start: ~str,
+    rules: ~[Rule],
+    /* the index of the start rule, the depth of the shallowest derivation of
+       each rule, and why the grammar can not generate, if it can not */
+    root: uint,
+    depths: ~[uint],
+    error: Option<~str>,
+}
+
+#[deriving(Clone)]
+struct Rule {
+    name: ~str,
+    alts: ~[Alt],
+}
+
+#[deriving(Clone)]
+struct Alt {
+    weight: uint,
+    symbols: ~[Symbol],
+}
+
+#[deriving(Clone)]
+enum Symbol {
+    Literal(~str),
+    Pattern(Regex),
+    /* a rule by name until it is defined, and then by index */
+    Ref(~str),
+    RuleIndex(uint),
+}
+
+/**
+ A derivation tree of a grammar. Its text is given by `to_str`.
+ */
+#[deriving(Eq, Clone)]
+pub enum Derivation {
+    /// Text of a literal or pattern
+    Leaf(~str),
+    /// The index of a rule and its alternative, and the derivations of its symbols
+    Branch(uint, uint, ~[Derivation]),
+}
+
+impl Derivation {
+    /// Return the number of nodes in the tree
+    pub fn nodes(&self) -> uint {
+        match *self {
+            Leaf(*) => 1,
+            Branch(_, _, ref ds) => ds.iter().fold(1u, |n, d| n + d.nodes()),
+        }
+    }
+
+    fn push_text(&self, s: &mut ~str) {
+        match *self {
+            Leaf(ref t) => s.push_str(*t),
+            Branch(_, _, ref ds) => {
+                for d in ds.iter() {
+                    d.push_text(s);
+                }
+            }
+        }
+    }
+}
+
+impl ToStr for Derivation {
+    fn to_str(&self) -> ~str {
+        let mut s = ~"";
+        self.push_text(&mut s);
+        s
+    }
+}
+
+impl Grammar {
+    /// Create an empty grammar deriving from the rule `start`
+    pub fn new(start: &str) -> Grammar {
+        let rules = Rules{start: start.to_owned(), rules: ~[], root: 0, depths: ~[],
+                          error: Some(fmt!("grammar: undefined rule '%s'", start))};
+        Grammar{rules: Arc::new(rules)}
+    }
+
+    /// Add `production` as an alternative of the rule `name`, with the given weight
+    pub fn rule(self, name: &str, weight: uint, production: &str) -> Grammar {
+        assert!(weight > 0);
+        let alt = Alt{weight: weight, symbols: parse_production(production)};
+        let mut t = self.rules.get().clone();
+        match t.rules.iter().position(|r| r.name.as_slice() == name) {
+            Some(i) => t.rules[i].alts.push(alt),
+            None => t.rules.push(Rule{name: name.to_owned(), alts: ~[alt]}),
+        }
+        t.resolve();
+        t.depths = t.shallowest();
+        t.error = t.check();
+        Grammar{rules: Arc::new(t)}
+    }
+
+    /// Generate a derivation, using the size factor as the depth budget
+    pub fn generate(&self, g: &mut Source) -> Derivation {
+        let t = self.rules.get();
+        match t.error {
+            Some(ref e) => fail!(e.clone()),
+            None => t.derive(t.root, g.size(), g),
+        }
+    }
+
+    /**
+     Shrink a derivation of this grammar.
+
+     Try the smallest derivation of the same rule, then subtrees deriving the
+     same rule, then shrink each subtree and each pattern's text in place.
+     */
+    pub fn shrink(&self, d: &Derivation) -> Lazy<Derivation> {
+        shrink_derivation(&self.rules, d)
+    }
+}
+
+impl Rules {
+    /// Refer to the rules defined by their index instead of their name
+    fn resolve(&mut self) {
+        let names: ~[~str] = self.rules.iter().map(|r| r.name.clone()).collect();
+        for rule in self.rules.mut_iter() {
+            for alt in rule.alts.mut_iter() {
+                for sym in alt.symbols.mut_iter() {
+                    let index = match *sym {
+                        Ref(ref name) => names.iter().position(|n| n == name),
+                        _ => None,
+                    };
+                    match index {
+                        Some(i) => *sym = RuleIndex(i),
+                        None => {}
+                    }
+                }
+            }
+        }
+        match names.iter().position(|n| *n == self.start) {
+            Some(i) => self.root = i,
+            None => {}
+        }
+    }
+
+    /// Return the depth of the shallowest derivation of each rule, or
+    /// `uint::max_value` for a rule that has none yet
+    fn shallowest(&self) -> ~[uint] {
+        let mut depths = std::vec::from_elem(self.rules.len(), std::uint::max_value);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (i, rule) in self.rules.iter().enumerate() {
+                for alt in rule.alts.iter() {
+                    let d = alt_depth(alt, depths);
+                    if d < depths[i] {
+                        depths[i] = d;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        depths
+    }
+
+    /// Return why the grammar can not generate: a rule that is referred to
+    /// and not defined, or that has no finite derivation
+    fn check(&self) -> Option<~str> {
+        for rule in self.rules.iter() {
+            for alt in rule.alts.iter() {
+                for sym in alt.symbols.iter() {
+                    match *sym {
+                        Ref(ref name) => {
+                            return Some(fmt!("grammar: undefined rule '%s'", *name));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        for (i, &d) in self.depths.iter().enumerate() {
+            if d == std::uint::max_value {
+                return Some(fmt!("grammar: rule '%s' has no finite derivation",
+                                 self.rules[i].name));
+            }
+        }
+        if !self.rules.iter().any(|r| r.name == self.start) {
+            return Some(fmt!("grammar: undefined rule '%s'", self.start));
+        }
+        None
+    }
+
+    fn derive(&self, r: uint, budget: uint, g: &mut Source) -> Derivation {
+        let rule = &self.rules[r];
+        /* the alternatives that fit the budget, or else the shallowest */
+        let budget = budget.max(&self.depths[r]);
+        let fits: ~[uint] = range(0, rule.alts.len())
+            .filter(|&a| alt_depth(&rule.alts[a], self.depths) <= budget).collect();
+        let total = fits.iter().fold(0u, |n, &a| n + rule.alts[a].weight);
+        let mut k = g.gen_index(total);
+        let mut choice = 0;
+        for &a in fits.iter() {
+            if k < rule.alts[a].weight {
+                choice = a;
+                break;
+            }
+            k -= rule.alts[a].weight;
+        }
+        let ds = do rule.alts[choice].symbols.map |sym| {
+            match *sym {
+                Literal(ref s) => Leaf(s.clone()),
+                Pattern(ref re) => Leaf(re.generate(g)),
+                RuleIndex(i) => self.derive(i, budget - 1, g),
+                Ref(ref name) => fail!(fmt!("grammar: undefined rule '%s'", *name)),
+            }
+        };
+        Branch(r, choice, ds)
+    }
+
+    /// Return the shallowest derivation of rule `r`
+    fn minimal(&self, r: uint) -> Derivation {
+        let mut g = Source::new(0, 0);
+        self.derive(r, 0, &mut g)
+    }
+}
+
+/// Return the depth of the shallowest derivation using `alt`, given the
+/// depths of the rules
+fn alt_depth(alt: &Alt, depths: &[uint]) -> uint {
+    let mut d = 1;
+    for sym in alt.symbols.iter() {
+        match *sym {
+            RuleIndex(i) => {
+                if depths[i] == std::uint::max_value {
+                    return depths[i];
+                }
+                d = d.max(&(depths[i] + 1));
+            }
+            /* rules not defined yet have no derivation */
+            Ref(*) => return std::uint::max_value,
+            _ => {}
+        }
+    }
+    d
+}
+
+/// Shrink `d`, a derivation of the grammar with `rules`, like `Grammar::shrink`
+fn shrink_derivation(rules: &Arc<Rules>, d: &Derivation) -> Lazy<Derivation> {
+    match *d {
+        Leaf(*) => Lazy::new(),
+        Branch(r, a, ref ds) => {
+            let mut v = ~[];
+            let m = rules.get().minimal(r);
+            /* only if smaller, so that shrinking can't go round in circles */
+            if m.nodes() < d.nodes() {
+                v.push(m);
+            }
+            same_rule(r, *ds, &mut v);
+            let mut L = Lazy::new_from(v);
+            push_child_shrinks(&mut L, rules.clone(), r, a, ds.clone(), 0);
+            L
+        }
+    }
+}
+
+/// Shrink `d`, a derivation of `sym`
+fn shrink_symbol(rules: &Arc<Rules>, sym: &Symbol, d: &Derivation) -> Lazy<Derivation> {
+    match (sym, d) {
+        (&RuleIndex(*), _) => shrink_derivation(rules, d),
+        (&Pattern(ref re), &Leaf(ref s)) => {
+            let mut L = Lazy::new();
+            L.push_map(re.shrink(s), |t| Leaf(t));
+            L
+        }
+        _ => Lazy::new(),
+    }
+}
+
+/// Push the subtrees of `ds` deriving rule `r`, nearest first
+fn same_rule(r: uint, ds: &[Derivation], v: &mut ~[Derivation]) {
+    for d in ds.iter() {
+        match *d {
+            Branch(s, _, _) if s == r => v.push(d.clone()),
+            _ => {}
+        }
+    }
+    for d in ds.iter() {
+        match *d {
+            Branch(s, _, ref sub) if s != r => same_rule(r, *sub, v),
+            _ => {}
+        }
+    }
+}
+
+/// Lazily push the shrinks of each of `ds` from `i` on, in place
+fn push_child_shrinks(L: &mut Lazy<Derivation>, rules: Arc<Rules>, r: uint, a: uint,
+                      ds: ~[Derivation], i: uint) {
+    do L.push_thunk((rules, r, a, ds, i)) |L, (rules, r, a, ds, i)| {
+        if i < ds.len() {
+            let shrinks = shrink_symbol(&rules, &rules.get().rules[r].alts[a].symbols[i],
+                                        &ds[i]);
+            do L.push_map_env(shrinks, (r, a, ds.clone(), i)) |d, env| {
+                let (r, a, ref ds, i) = *env;
+                let mut ds = ds.clone();
+                ds[i] = d;
+                Branch(r, a, ds)
+            }
+            push_child_shrinks(L, rules, r, a, ds, i + 1);
+        }
+    }
+}
+
+/// Split a production into its symbols
+fn parse_production(production: &str) -> ~[Symbol] {
+    let chars: ~[char] = production.iter().collect();
+    let mut syms = ~[];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == ' ' {
+            i += 1;
+        } else if c == '\'' || c == '/' {
+            /* text up to the closing quote or slash, which may be escaped */
+            let mut s = ~"";
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' && i + 1 < chars.len() {
+                    if chars[i + 1] != c && (c == '/' || chars[i + 1] != '\\') {
+                        s.push_char('\\');
+                    }
+                    i += 1;
+                }
+                s.push_char(chars[i]);
+                i += 1;
+            }
+            if i == chars.len() {
+                fail!(fmt!("grammar: unterminated %c in '%s'", c, production));
+            }
+            i += 1;
+            syms.push(if c == '/' { Pattern(Regex::new(s)) } else { Literal(s) });
+        } else {
+            let start = i;
+            while i < chars.len() && chars[i] != ' ' {
+                i += 1;
+            }
+            syms.push(Ref(std::str::from_chars(chars.slice(start, i))));
+        }
+    }
+    syms
+}
+
+#[test]
+fn test_grammar() {
+    let gr = Grammar::new("list")
+        .rule("list", 1, "'[' ']'")
+        .rule("list", 4, "'[' items ']'")
+        .rule("items", 2, "item")
+        .rule("items", 1, "item ', ' items")
+        .rule("item", 3, "/[a-z]\\/[0-9]/")
+        .rule("item", 1, "list")
+        .rule("item", 1, "'it\\'s'");
+
+    let mut g = Source::new(0, 8);
+    for _ in range(0, 100) {
+        let d = gr.generate(&mut g);
+        let s = d.to_str();
+        assert!(s.starts_with("[") && s.ends_with("]"));
+        for e in gr.shrink(&d) {
+            assert!(e != d);
+            let t = e.to_str();
+            assert!(t.starts_with("[") && t.ends_with("]"));
+        }
+    }
+
+    /* Without a budget only the shallowest alternatives are used */
+    let mut g = Source::new(0, 0);
+    for _ in range(0, 100) {
+        assert_eq!(gr.generate(&mut g).to_str(), ~"[]");
+    }
+
+    let d = Branch(0, 1, ~[Leaf(~"["), Branch(1, 0, ~[Branch(2, 2, ~[Leaf(~"it's")])]),
+                           Leaf(~"]")]);
+    assert_eq!(d.nodes(), 6);
+    assert_eq!(gr.shrink(&d).next(), Some(Branch(0, 0, ~[Leaf(~"["), Leaf(~"]")])));
+}
+
+#[test]
+#[should_fail]
+fn test_grammar_undefined() {
+    let gr = Grammar::new("list").rule("list", 1, "'[' items ']'");
+    gr.generate(&mut Source::new(0, 8));
+}
@@ -17,6 +17,12 @@ Example::
             |mut v: ~[u8]| { sort(&mut v); is_sorted(v) });
     }
 
+`quick_check` calls the property in the current task. A `&fn` closure borrows
+its environment and can not be sent to another task, so it can not be isolated:
+a property that fails its task fails the whole run, and it can not be timed
+out. Check properties that may fail or hang with `quick_check_isolated`, which
+takes an owned closure and evaluates it in a task of its own.
+
 Issues:
 
 * Clean up Lazy and Shrink, implement Arbitrary and Shrink further
@@ -39,17 +45,35 @@ according to those terms.
 
 #[crate_type="lib"];
 
-#[cfg(test)]
 extern mod extra;
 
 pub use lazy::Lazy;
 pub use shrink::Shrink;
-pub use arbitrary::{Arbitrary, arbitrary, SmallN};
+pub use arbitrary::{Arbitrary, arbitrary, SmallN, Source};
+pub use testable::{Testable, TestResult, Pass, Fail, Discard, implies};
+pub use rose::{Rose, Kids};
+pub use gen::Gen;
+pub use regex::Regex;
+pub use grammar::{Grammar, Derivation};
+
+use shrink::shrink_elements;
+use choices::shrink_choices;
+use std::cell::Cell;
+use std::rand::RngUtil;
+use std::unstable::atomics::{AtomicBool, AtomicUint, INIT_ATOMIC_BOOL, INIT_ATOMIC_UINT, SeqCst};
+use extra::arc::Arc;
 
 
 mod lazy;
 mod shrink;
 mod arbitrary;
+mod testable;
+mod tuple;
+mod rose;
+mod choices;
+pub mod gen;
+pub mod regex;
+pub mod grammar;
 
 
 pub struct QConfig {
@@ -57,13 +81,33 @@ pub struct QConfig {
     size: uint,
     verbose: bool,
     grow: bool,
+    seed: Option<uint>,
+    trial: Option<uint>,
+    max_discard: uint,
+    timeout: Option<u64>,
+    boundary: uint,
+    choices: bool,
+    max_shrinks: Option<uint>,
+    max_shrink_evals: Option<uint>,
+    shrink_time: Option<u64>,
+    max_timeouts: uint,
+    trace: bool,
+    deep: bool,
+    restarts: uint,
 }
 
 /** Default config value */
-pub static config: QConfig = QConfig{ trials: 50, size: 8, verbose: false, grow: true };
+pub static config: QConfig = QConfig{ trials: 50, size: 8, verbose: false, grow: true,
+                                      seed: None, trial: None, max_discard: 10,
+                                      timeout: None, boundary: 10, choices: true,
+                                      max_shrinks: None, max_shrink_evals: None,
+                                      shrink_time: None, max_timeouts: 10,
+                                      trace: false, deep: false, restarts: 0 };
 
 impl QConfig {
-    /// Set size factor (default 8)
+    /// Set size factor (default 8). Lengths of containers and magnitudes of
+    /// numbers scale with it; apart from boundary values, generated integers
+    /// are at most 16 times the size factor.
     pub fn size(self, x: uint) -> QConfig {
         QConfig{size: x, ..self}
     }
@@ -79,6 +123,471 @@ impl QConfig {
     pub fn verbose(self, x: bool) -> QConfig {
         QConfig{verbose: x, ..self}
     }
+    /// Set random seed (default: a new random seed for each run)
+    pub fn seed(self, x: uint) -> QConfig {
+        QConfig{seed: Some(x), ..self}
+    }
+    /// Set how many discarded values per trial are allowed before giving up
+    /// (default 10)
+    pub fn max_discard(self, x: uint) -> QConfig {
+        QConfig{max_discard: x, ..self}
+    }
+    /// Set the time limit for each evaluation of the property by
+    /// `check_isolated`, in milliseconds (default none). `check` and the
+    /// other entry points evaluate the property in the current task, where
+    /// it can not be timed out, and ignore it.
+    pub fn timeout(self, ms: u64) -> QConfig {
+        QConfig{timeout: Some(ms), ..self}
+    }
+    /// Set the percentage of generated numbers that are boundary values
+    /// like 0, -1, MAX and MIN (default 10)
+    pub fn boundary(self, percent: uint) -> QConfig {
+        QConfig{boundary: percent, ..self}
+    }
+    /// Set if `check` should shrink counterexamples through the random choices
+    /// that generated them, like `check_choices`, before shrinking them
+    /// further with `Shrink`, instead of only with `Shrink` (default true)
+    pub fn choices(self, x: bool) -> QConfig {
+        QConfig{choices: x, ..self}
+    }
+    /// Set the maximum number of successful shrink steps (default none)
+    pub fn max_shrinks(self, x: uint) -> QConfig {
+        QConfig{max_shrinks: Some(x), ..self}
+    }
+    /// Set the maximum number of evaluations of the property while shrinking
+    /// (default none)
+    pub fn max_shrink_evals(self, x: uint) -> QConfig {
+        QConfig{max_shrink_evals: Some(x), ..self}
+    }
+    /// Set the time limit for shrinking a counterexample, in milliseconds
+    /// (default none)
+    pub fn shrink_time(self, ms: u64) -> QConfig {
+        QConfig{shrink_time: Some(ms), ..self}
+    }
+    /// Set the maximum number of values that may time out while shrinking a
+    /// counterexample with a `timeout` (default 10). Each one leaves its task
+    /// running.
+    pub fn max_timeouts(self, x: uint) -> QConfig {
+        QConfig{max_timeouts: x, ..self}
+    }
+    /// Set if a counterexample should include the trace of its shrink steps
+    /// (default false)
+    pub fn trace(self, x: bool) -> QConfig {
+        QConfig{trace: x, ..self}
+    }
+    /// Set if shrinking should try deeper strategies when no shrink of a
+    /// counterexample fails: `shrink_deep`, and then shrinks of shrinks, up to
+    /// `PAIR_CANDIDATES` of them (default false).
+    pub fn deep(self, x: bool) -> QConfig {
+        QConfig{deep: x, ..self}
+    }
+    /// Set how many times shrinking restarts from the counterexample with the
+    /// shrinks of each value tried in a shuffled order (default 0). The first
+    /// failing shrink is taken at each step, so another order can reach
+    /// another minimal counterexample; the one reached by the most shrink
+    /// steps composed is kept. The orders are shuffled with the seed of the
+    /// run, so they are the same when it is replayed.
+    pub fn restarts(self, n: uint) -> QConfig {
+        QConfig{restarts: n, ..self}
+    }
+    /// Run only trial number `trial` of the run using `seed`,
+    /// as printed when a property is falsified.
+    pub fn replay(self, seed: uint, trial: uint) -> QConfig {
+        QConfig{seed: Some(seed), trial: Some(trial), ..self}
+    }
+
+    /**
+     Return the seed for a run and the trial to replay, if any.
+
+     The environment variable `QC_SEED` overrides the configured seed.
+     It is either `QC_SEED=<seed>` or `QC_SEED=<seed>:<trial>`.
+     */
+    fn run_seed(&self) -> (uint, Option<uint>) {
+        match env_seed() {
+            Some(x) => x,
+            None => (match self.seed {
+                        Some(s) => s,
+                        None => std::rand::random(),
+                    }, self.trial),
+        }
+    }
+
+    /// Size factor for trial number `i`
+    fn trial_size(&self, i: uint) -> uint {
+        self.size + if self.grow { i / 8 } else { 0 }
+    }
+
+    /// The Source for trial number `i` of a run using `seed`
+    fn trial_source(&self, seed: uint, i: uint) -> Source {
+        let mut g = Source::for_trial(seed, i, self.trial_size(i));
+        g.set_boundary(self.boundary);
+        g
+    }
+
+    /// The shrink limit reached after `steps` shrink steps and `evals`
+    /// evaluations, when shrinking started at `start` (in ns), if any
+    fn shrink_limit(&self, stats: &ShrinkStats, start: u64) -> Option<ShrinkLimit> {
+        match self.max_shrinks {
+            Some(n) if stats.steps >= n => return Some(StepLimit(n)),
+            _ => {}
+        }
+        match self.max_shrink_evals {
+            Some(n) if stats.evaluated >= n => return Some(EvalLimit(n)),
+            _ => {}
+        }
+        if stats.timed_out > 0 && stats.timed_out >= self.max_timeouts {
+            return Some(TimedOut(self.max_timeouts));
+        }
+        match self.shrink_time {
+            Some(ms) if extra::time::precise_time_ns() - start >= ms * 1000000 => {
+                Some(TimeLimit(ms))
+            }
+            _ => None,
+        }
+    }
+
+    /// The Source making `choices`, for a trial with size factor `size`
+    fn choice_source(&self, choices: ~[u32], size: uint) -> Source {
+        let mut g = Source::from_choices(choices, size);
+        g.set_boundary(self.boundary);
+        g
+    }
+}
+
+/// Parse the QC_SEED environment variable, if set
+fn env_seed() -> Option<(uint, Option<uint>)> {
+    let s = match std::os::getenv("QC_SEED") {
+        None => return None,
+        Some(s) => s,
+    };
+    let parts = s.split_iter(':').collect::<~[&str]>();
+    let num = |x: &str| match from_str::<uint>(x.trim()) {
+        Some(n) => n,
+        None => fail!(fmt!("qc: invalid QC_SEED '%s'", s)),
+    };
+    match parts.len() {
+        1 => Some((num(parts[0]), None)),
+        2 => Some((num(parts[0]), Some(num(parts[1])))),
+        _ => fail!(fmt!("qc: invalid QC_SEED '%s'", s)),
+    }
+}
+
+/// The outcome of checking a property with `check`
+pub enum Outcome<A> {
+    /// The property held for all trials; the number of trials run
+    Passed(uint),
+    /// A counterexample was found
+    Falsified(Counterexample<A>),
+    /// Too many values were discarded; the number of trials passed
+    /// and the number of values discarded
+    GaveUp(uint, uint),
+}
+
+/// A counterexample to a property, as found by `check`
+pub struct Counterexample<A> {
+    /// The value first found to falsify the property
+    original: A,
+    /// The minimal value found by shrinking `original`
+    shrunk: A,
+    /// Statistics of shrinking `original` to `shrunk`
+    shrink: ShrinkStats,
+    /// Each shrink step from `original` to `shrunk`, if `trace` is configured
+    trace: ~[ShrinkStep<A>],
+    /// Number of trials passed before the falsifying one, plus one
+    trials: uint,
+    /// Seed of the run
+    seed: uint,
+    /// Index of the falsifying trial
+    trial: uint,
+    /// Reason the property failed for `shrunk`
+    reason: ~str,
+    /// The limit that stopped shrinking before `shrunk` was minimal, if any
+    shrink_limit: Option<ShrinkLimit>,
+}
+
+/// Statistics of shrinking a counterexample
+#[deriving(Eq, Clone)]
+pub struct ShrinkStats {
+    /// Number of candidates evaluated
+    evaluated: uint,
+    /// Number of successful shrink steps
+    steps: uint,
+    /// Depth reached in the tree of shrinks: the number of single shrinks
+    /// composed to get the shrunk value
+    depth: uint,
+    /// Number of values for which the property timed out, including the
+    /// counterexample if it did
+    timed_out: uint,
+    /// Time spent shrinking, in nanoseconds
+    time_ns: u64,
+}
+
+impl ToStr for ShrinkStats {
+    fn to_str(&self) -> ~str {
+        let mut s = fmt!("%u steps to depth %u, %u candidates evaluated in %u ms",
+                         self.steps, self.depth, self.evaluated,
+                         (self.time_ns / 1000000) as uint);
+        if self.timed_out > 0 {
+            s.push_str(fmt!(", %u timed out", self.timed_out));
+        }
+        s
+    }
+}
+
+/// A successful shrink step
+#[deriving(Clone)]
+pub struct ShrinkStep<A> {
+    /// The smaller counterexample
+    value: A,
+    /// Number of candidates evaluated in this step, including `value`
+    candidates: uint,
+    /// Reason the property failed for `value`
+    reason: ~str,
+}
+
+/// The maximum number of shrinks of shrinks tried in one step of deep shrinking
+pub static PAIR_CANDIDATES: uint = 1000;
+
+/// The state and result of shrinking a counterexample node with `shrink_count`
+#[deriving(Clone)]
+struct Shrunk<N> {
+    node: N,
+    res: TestResult,
+    limit: Option<ShrinkLimit>,
+    stats: ShrinkStats,
+    trace: ~[ShrinkStep<N>],
+    /* candidates evaluated since the last step, and the start time in ns */
+    tried: uint,
+    start: u64,
+}
+
+/// A limit of `QConfig` that cuts shrinking short
+#[deriving(Eq, Clone)]
+pub enum ShrinkLimit {
+    /// The maximum number of shrink steps
+    StepLimit(uint),
+    /// The maximum number of evaluations
+    EvalLimit(uint),
+    /// The time limit, in milliseconds
+    TimeLimit(u64),
+    /// The maximum number of values for which the property timed out
+    TimedOut(uint),
+}
+
+impl ToStr for ShrinkLimit {
+    fn to_str(&self) -> ~str {
+        match *self {
+            StepLimit(n) => fmt!("the limit of %u shrink steps", n),
+            EvalLimit(n) => fmt!("the limit of %u evaluations", n),
+            TimeLimit(ms) => fmt!("the time limit of %u ms", ms as uint),
+            TimedOut(n) => fmt!("the limit of %u values that timed out", n),
+        }
+    }
+}
+
+impl<A> Outcome<A> {
+    /// Return true if the property passed
+    pub fn is_passed(&self) -> bool {
+        match *self {
+            Passed(*) => true,
+            _ => false,
+        }
+    }
+}
+
+/**
+
+ Repeatedly test `property` with values of type `A` chosen using `Arbitrary`,
+ and return the `Outcome`.
+
+ Only trials that are not discarded count towards the configured number of
+ trials. If more than `max_discard` values per trial are discarded, check gives
+ up.
+
+ If a counterexample is found, check shrinks it to try to find a minimal
+ counterexample, and returns both the original and the shrunk value. It is
+ shrunk through the random choices that generated it, like `check_choices`,
+ and then further like `quick_shrink`. With `choices(false)`, it is only
+ shrunk like `quick_shrink`.
+
+ `prop` is called in the current task, so a property that fails its task
+ fails the caller too, and a configured `timeout` does not apply. Use
+ `check_isolated` for properties that may fail or hang.
+
+ `name` is only used for verbose output.
+ */
+pub fn check<A: Clone + Shrink + Arbitrary, R: Testable>(name: &str, cfg: QConfig,
+                                                        prop: &fn(A) -> R) -> Outcome<A> {
+    check_test(name, cfg, |v| (prop(v).result(), false))
+}
+
+/**
+ Like `check`, but each evaluation of `prop` runs in its own task, and a
+ property that fails its task is falsified by that value, with the message of
+ the failure as the reason. If a `timeout` is configured, a property that does
+ not finish in time is falsified too.
+
+ To be sent to other tasks, `prop` owns what it captures, which must be
+ sendable and immutable, and so must the values tested.
+
+ Shrinking goes on past values that time out, evaluating each candidate with
+ the same timeout. A task that times out can not be stopped and keeps
+ running, so shrinking stops once `max_timeouts` values have timed out, and
+ a property that never returns keeps the process from exiting.
+ */
+pub fn check_isolated<A: Send + Clone + Shrink + Arbitrary, R: Testable>(name: &str, cfg: QConfig,
+                                                                        prop: ~fn:Send+Freeze(A) -> R)
+                                                                        -> Outcome<A> {
+    let prop = Isolated::new(prop);
+    check_test(name, cfg, |v| evaluate(cfg, &prop, v))
+}
+
+/// Check `test` like `check`
+fn check_test<A: Clone + Shrink + Arbitrary>(name: &str, cfg: QConfig,
+                                             test: &fn(A) -> (TestResult, bool)) -> Outcome<A> {
+    if cfg.choices {
+        match run_choices(name, cfg, |g| arbitrary(g), |v| test(v)) {
+            Falsified(c) => Falsified(shrink_further(cfg, c, test)),
+            outcome => outcome,
+        }
+    } else {
+        run(name, cfg, |g| arbitrary(g), |v| v.clone(), |v| v.shrink(), |v| v.shrink_deep(), test)
+    }
+}
+
+/**
+ Like `check`, but values are generated by `gen` and counterexamples are
+ shrunk by `shrink`, instead of using the `Arbitrary` and `Shrink` impls of
+ `A`.
+ */
+pub fn check_with<A: Clone, R: Testable>(name: &str, cfg: QConfig,
+                                        gen: &fn(&mut Source) -> A,
+                                        shrink: &fn(&A) -> Lazy<A>,
+                                        prop: &fn(A) -> R) -> Outcome<A> {
+    run(name, cfg, gen, |v| v.clone(), shrink, |_| Lazy::new(), |v| (prop(v).result(), false))
+}
+
+/**
+ Like `check`, but `gen` generates each value together with the tree of its
+ shrinks (integrated shrinking), so that a counterexample only shrinks to
+ values `gen` could have generated.
+ */
+pub fn check_rose<A: 'static + Clone, R: Testable>(name: &str, cfg: QConfig,
+                                                  gen: &fn(&mut Source) -> Rose<A>,
+                                                  prop: &fn(A) -> R) -> Outcome<A> {
+    run(name, cfg, gen, |r| r.value.clone(), |r| r.children(), |_| Lazy::new(),
+        |v| (prop(v).result(), false))
+}
+
+/**
+ Like `check`, but values are generated by `gen` and counterexamples are
+ shrunk through the random choices `gen` made: the choices are shrunk, and
+ `gen` generates again from them. This needs no `Shrink` impl, and shrunk
+ values are always ones `gen` could have generated.
+ */
+pub fn check_choices<A: Clone, R: Testable>(name: &str, cfg: QConfig,
+                                           gen: &fn(&mut Source) -> A,
+                                           prop: &fn(A) -> R) -> Outcome<A> {
+    run_choices(name, cfg, gen, |v| (prop(v).result(), false))
+}
+
+/// Check `test` like `check_choices`
+fn run_choices<A: Clone>(name: &str, cfg: QConfig, gen: &fn(&mut Source) -> A,
+                         test: &fn(A) -> (TestResult, bool)) -> Outcome<A> {
+    run(name, cfg,
+        |g| { g.record_choices(); gen(g); (g.size(), g.choices()) },
+        |&(size, ref c)| gen(&mut cfg.choice_source(c.clone(), size)),
+        |&(size, ref c)| {
+            let mut L = Lazy::new();
+            L.push_map_env(shrink_choices(c), size, |c, size| (*size, c));
+            L
+        },
+        |_| Lazy::new(),
+        test)
+}
+
+/// Shrink the counterexample `c` further with `Shrink`, unless a limit stopped
+/// shrinking it
+fn shrink_further<A: Clone + Shrink>(cfg: QConfig, c: Counterexample<A>,
+                                     test: &fn(A) -> (TestResult, bool)) -> Counterexample<A> {
+    if c.shrink_limit.is_some() {
+        return c;
+    }
+    let Counterexample{original, shrunk, shrink, trace, trials, seed, trial, reason, _} = c;
+    let start = extra::time::precise_time_ns() - shrink.time_ns;
+    let s = Shrunk{node: shrunk, res: Fail(reason), limit: None, stats: shrink,
+                   trace: ~[], tried: 0, start: start};
+    let Shrunk{node: shrunk, res, limit, stats, trace: steps, _} =
+        shrink_from(cfg, s, seed, |v| v.shrink(), |v| v.shrink_deep(), test, |v| v.clone());
+    let mut trace = trace;
+    trace.push_all_move(steps);
+    Counterexample{original: original, shrunk: shrunk, shrink: stats, trace: trace,
+                   trials: trials, seed: seed, trial: trial, reason: res.reason(),
+                   shrink_limit: limit}
+}
+
+/// Test the values of nodes generated by `gen` with `test`, and shrink the
+/// node of a counterexample with `shrink` and `deep`. `test` also returns
+/// true if the test of the value timed out.
+fn run<N: Clone, A: Clone, I: Iterator<N>, J: Iterator<N>>(
+        name: &str, cfg: QConfig,
+        gen: &fn(&mut Source) -> N,
+        value: &fn(&N) -> A,
+        shrink: &fn(&N) -> I,
+        deep: &fn(&N) -> J,
+        test: &fn(A) -> (TestResult, bool)) -> Outcome<A> {
+    let (seed, replay) = cfg.run_seed();
+    let (mut i, trials, max_discard) = match replay {
+        Some(t) => (t, 1, 0),
+        None => (0, cfg.trials, cfg.trials * cfg.max_discard),
+    };
+    let mut passed = 0u;
+    let mut discarded = 0u;
+    while passed < trials {
+        let mut g = cfg.trial_source(seed, i);
+        let node = gen(&mut g);
+        match test(value(&node)) {
+            (Pass, _) => passed += 1,
+            (Discard, _) => {
+                discarded += 1;
+                if discarded > max_discard {
+                    if cfg.verbose {
+                        println(fmt!("qc %s: gave up after %u discards (seed %u)",
+                                     name, discarded, seed));
+                    }
+                    return GaveUp(passed, discarded);
+                }
+            }
+            (res, hung) => {
+                if cfg.verbose {
+                    println(fmt!("qc %s: first falsification with value '%?'",
+                                 name, &value(&node)));
+                }
+                let Shrunk{node: shrunk, res, limit, stats, trace, _} =
+                    shrink_count(cfg, node.clone(), res, hung, seed, shrink, deep,
+                                 |n| test(value(&n)), value);
+                let trace = trace.move_iter().map(|ShrinkStep{value: n, candidates, reason}| {
+                    ShrinkStep{value: value(&n), candidates: candidates, reason: reason}
+                }).collect();
+                return Falsified(Counterexample{
+                    original: value(&node),
+                    shrunk: value(&shrunk),
+                    shrink: stats,
+                    trace: trace,
+                    trials: 1 + passed,
+                    seed: seed,
+                    trial: i,
+                    reason: res.reason(),
+                    shrink_limit: limit,
+                });
+            }
+        }
+        i += 1;
+    }
+    if cfg.verbose {
+        println(fmt!("qc %s: passed (seed %u)", name, seed));
+    }
+    Passed(passed)
 }
 
 /**
@@ -90,8 +599,16 @@ impl QConfig {
  If a counterexample is found, quick_check will use `quick_shrink` to try to
  find a minimal counterexample to `property`.
 
+ `property` is a `&fn` that can not be sent to another task, so it is called
+ in the current task like with `check`: if it fails its task, quick_check
+ fails with that failure instead of reporting a counterexample, and a
+ configured `timeout` does not apply. Use `quick_check_isolated` for
+ properties that may fail or hang.
+
  quick_check calls `fail!()` with an error message indicating `name` and the
- repr of the counterexample.
+ repr of the counterexample, and the seed and trial index that produced it.
+ Replay that case with `config.replay(seed, trial)`, or without recompiling by
+ setting the environment variable `QC_SEED=<seed>:<trial>`.
  
  Examples:
  
@@ -99,68 +616,696 @@ impl QConfig {
 
  `quick_check("name", config, |x: Type| property(x));`
 
- `quick_check("str", config.trials(100), |s: ~str| s.is_ascii());`
+ `quick_check("str", config.trials(100), |s: ~str| s.len() >= s.char_len());`
  
  NOTE: `A` must implement `Clone`.
  */
-pub fn quick_check<A: Clone + Shrink + Arbitrary>(name: &str, cfg: QConfig, prop: &fn(A) -> bool) {
-    let mut i = 0;
-    while i < cfg.trials {
-        let value = arbitrary::<A>(cfg.size + if cfg.grow { i / 8 } else { 0 });
-        let v_copy = value.clone();
-        if !prop(value) {
-            if cfg.verbose {
-                println(fmt!("qc %s: first falsification with value '%?'", name, &v_copy));
+pub fn quick_check<A: Clone + Shrink + Arbitrary, R: Testable>(name: &str, cfg: QConfig,
+                                                              prop: &fn(A) -> R) {
+    report(name, check(name, cfg, prop))
+}
+
+/**
+ Like `quick_check`, but each evaluation of `property` runs in its own task,
+ like `check_isolated`. Use it for properties that may fail or hang.
+
+ Example:
+
+ `quick_check_isolated!(config.timeout(100), |v: ~[int]| parse(v).is_ok());`
+ */
+pub fn quick_check_isolated<A: Send + Clone + Shrink + Arbitrary, R: Testable>(name: &str, cfg: QConfig,
+                                                                              prop: ~fn:Send+Freeze(A) -> R) {
+    report(name, check_isolated(name, cfg, prop))
+}
+
+/**
+ Repeatedly test `property` with values generated by `gen`.
+
+ Like `quick_check`, but with an explicit generator instead of `Arbitrary`.
+ Counterexamples are shrunk using the shrinks `gen` builds along with each
+ value, so they always satisfy the constraints of `gen`.
+
+ Examples:
+
+ `for_all!(gen::choose(1, 6), |x: int| property(x));`
+
+ `for_all("name", config, &gen::vec_of(gen::elements(&[1, 2, 3])), |v: ~[int]| property(v));`
+ */
+pub fn for_all<A: 'static + Clone, R: Testable>(name: &str, cfg: QConfig, gen: &Gen<A>,
+                                               prop: &fn(A) -> R) {
+    report(name, check_rose(name, cfg, |g| gen.generate_rose(g), prop))
+}
+
+/**
+ Repeatedly test `property` with values generated by `gen`, shrinking
+ counterexamples with `shrink`.
+
+ Like `quick_check`, but for types that don't implement `Arbitrary` and
+ `Shrink`.
+
+ Examples:
+
+ `for_all_shrink!(|g| Foreign::new(arbitrary(g)), |x| shrink_foreign(x), |x| property(x));`
+ */
+pub fn for_all_shrink<A: Clone, R: Testable>(name: &str, cfg: QConfig,
+                                            gen: &fn(&mut Source) -> A,
+                                            shrink: &fn(&A) -> Lazy<A>,
+                                            prop: &fn(A) -> R) {
+    report(name, check_with(name, cfg, gen, shrink, prop))
+}
+
+/**
+ Like `quick_check`, but for types that implement `Arbitrary` and not
+ `Shrink`. Counterexamples are shrunk like `check_choices`.
+
+ Example:
+
+ `quick_check_choices!(|x: Foreign| property(x));`
+ */
+pub fn quick_check_choices<A: Clone + Arbitrary, R: Testable>(name: &str, cfg: QConfig,
+                                                             prop: &fn(A) -> R) {
+    report(name, check_choices(name, cfg, |g| arbitrary(g), prop))
+}
+
+/**
+ Like `quick_check`, but `property` borrows a slice of the generated values.
+ The runner owns the storage, which is generated and shrunk as a `~[T]`.
+
+ Example:
+
+ `quick_check_slice!(|v: &[int]| v.iter().max() == v.iter().invert().max());`
+ */
+pub fn quick_check_slice<T: Send + Clone + Shrink + Arbitrary, R: Testable>(name: &str, cfg: QConfig,
+                                                                          prop: &fn(&[T]) -> R) {
+    report(name, check(name, cfg, |v: ~[T]| prop(v)))
+}
+
+/**
+ Like `quick_check`, but `property` borrows a string owned by the runner.
+
+ Example:
+
+ `quick_check_str!(|s: &str| s.char_len() <= s.len());`
+ */
+pub fn quick_check_str<R: Testable>(name: &str, cfg: QConfig, prop: &fn(&str) -> R) {
+    report(name, check(name, cfg, |s: ~str| prop(s)))
+}
+
+/**
+ Like `quick_check_slice`, but the slice always has length `n`. Shrinking
+ shrinks the elements, never the length.
+
+ Fixed-size arrays implement `Arbitrary` and `Shrink`, but not `Clone`,
+ which `quick_check` and `quick_shrink` need, so check properties of
+ `[T, ..n]` with this instead.
+
+ Example:
+
+ `quick_check_array!(4, |v: &[u8]| v.len() == 4);`
+ */
+pub fn quick_check_array<T: Send + Clone + Shrink + Arbitrary, R: Testable>(name: &str, cfg: QConfig,
+                                                                          n: uint,
+                                                                          prop: &fn(&[T]) -> R) {
+    report(name, check_with(name, cfg,
+                            |g| std::vec::from_fn(n, |_| arbitrary(g)),
+                            |v| shrink_elements(v.clone()),
+                            |v: ~[T]| prop(v)))
+}
+
+/// Fail with a message describing `outcome`, unless it passed
+fn report<A>(name: &str, outcome: Outcome<A>) {
+    match failure_report(name, outcome) {
+        Some(msg) => fail!(msg),
+        None => {}
+    }
+}
+
+/// The message `report` fails with for `outcome`, if it did not pass
+fn failure_report<A>(name: &str, outcome: Outcome<A>) -> Option<~str> {
+    match outcome {
+        Passed(*) => None,
+        Falsified(Counterexample{shrunk, shrink, trace, trials, seed, trial, reason,
+                                 shrink_limit, _}) => {
+            let cut = match shrink_limit {
+                Some(l) => fmt!(" (smallest found before shrinking reached %s)", l.to_str()),
+                None => ~"",
+            };
+            let mut msg = fmt!("qc %s: falsified (%u trials) with value '%?'%s: %s (seed %u, trial %u)",
+                               name, trials, shrunk, cut, reason, seed, trial);
+            msg.push_str(fmt!("\n  shrinking: %s", shrink.to_str()));
+            for (i, step) in trace.move_iter().enumerate() {
+                msg.push_str(fmt!("\n  shrink step %u: '%?' after %u candidates: %s",
+                                  i + 1, step.value, step.candidates, step.reason));
+            }
+            Some(msg)
+        }
+        GaveUp(n, d) => {
+            Some(fmt!("qc %s: gave up after %u discards (%u trials passed)", name, d, n))
+        }
+    }
+}
+
+/**
+ A property that can be evaluated in other tasks, for `isolate`.
+ It owns what it captures, which must be sendable and immutable.
+ */
+pub struct Isolated<A, R> {
+    priv f: Arc<~fn:Send+Freeze(A) -> R>,
+}
+
+impl<A, R> Isolated<A, R> {
+    pub fn new(f: ~fn:Send+Freeze(A) -> R) -> Isolated<A, R> {
+        Isolated{f: Arc::new(f)}
+    }
+}
+
+impl<A, R> Clone for Isolated<A, R> {
+    fn clone(&self) -> Isolated<A, R> {
+        Isolated{f: self.f.clone()}
+    }
+}
+
+/**
+ Evaluate `prop` for `value` in a new task.
+
+ If the task fails, the result is a failure, with the message of the task
+ failure as its reason.
+ */
+pub fn isolate<A: Send, R: Testable>(prop: &Isolated<A, R>, value: A) -> TestResult {
+    let name = task_name();
+    let value = Cell::new(value);
+    let (res, text) = capture_stderr(|| try_named(prop.clone(), value.take(), name.clone()));
+    task_result(res, text, name)
+}
+
+/**
+ Evaluate `prop` for `value` in a new task, like `isolate`, but fail the test
+ if it does not finish within `ms` milliseconds.
+
+ A task can not be stopped from the outside, so a task that times out is
+ left running in the background.
+ */
+pub fn isolate_timeout<A: Send, R: Testable>(prop: &Isolated<A, R>, value: A, ms: u64) -> TestResult {
+    match isolate_within(prop, value, ms) {
+        Some(r) => r,
+        None => Fail(fmt!("timed out after %u ms", ms as uint)),
+    }
+}
+
+/// Evaluate `prop` for `value` like `isolate_timeout`, returning None if it
+/// timed out
+fn isolate_within<A: Send, R: Testable>(prop: &Isolated<A, R>, value: A, ms: u64) -> Option<TestResult> {
+    let name = task_name();
+    let (port, chan) = std::comm::stream();
+    let args = Cell::new((prop.clone(), value, name.clone(), chan));
+    let (res, text) = do capture_stderr {
+        let mut t = std::task::task();
+        t.unlinked();
+        /* Use a separate scheduler so that a busy property can't starve us */
+        t.sched_mode(std::task::SingleThreaded);
+        let args = Cell::new(args.take());
+        do t.spawn {
+            let (prop, value, name, chan) = args.take();
+            chan.try_send(try_named(prop, value, name));
+        }
+        let deadline = extra::time::precise_time_ns() + ms * 1000000;
+        let mut res = None;
+        while res.is_none() && extra::time::precise_time_ns() < deadline {
+            if port.peek() {
+                res = Some(port.recv());
+            } else {
+                std::task::yield();
+            }
+        }
+        res
+    };
+    match res {
+        Some(r) => Some(task_result(r, text, name)),
+        None => None,
+    }
+}
+
+/// Evaluate `prop` for `value` in a new task, with the configured timeout if
+/// any, and return the result and true if it timed out
+fn evaluate<A: Send, R: Testable>(cfg: QConfig, prop: &Isolated<A, R>,
+                                  value: A) -> (TestResult, bool) {
+    match cfg.timeout {
+        Some(ms) => match isolate_within(prop, value, ms) {
+            Some(r) => (r, false),
+            None => (Fail(fmt!("timed out after %u ms", ms as uint)), true),
+        },
+        None => (isolate(prop, value), false),
+    }
+}
+
+/// Evaluate `prop` for `value` in a new task named `name`
+fn try_named<A: Send, R: Testable>(prop: Isolated<A, R>, value: A,
+                                   name: ~str) -> Result<TestResult, ()> {
+    let value = Cell::new(value);
+    let prop = Cell::new(prop);
+    let mut t = std::task::task();
+    t.name(name);
+    do t.try {
+        let prop = prop.take();
+        (*prop.f.get())(value.take()).result()
+    }
+}
+
+/// The result of the task `name`, given what was written to standard error
+/// while it ran
+fn task_result(res: Result<TestResult, ()>, text: &str, name: &str) -> TestResult {
+    match res {
+        Ok(r) => r,
+        Err(()) => Fail(match failure_message(text, name) {
+            Some(msg) => msg,
+            None => ~"task failed",
+        }),
+    }
+}
+
+/// Number of isolated tasks, to give each a name of its own
+static mut TASKS: AtomicUint = INIT_ATOMIC_UINT;
+/// Held while the capture of standard error is started or ended
+static mut CAPTURE_LOCK: AtomicBool = INIT_ATOMIC_BOOL;
+/// Number of running captures of standard error, and the descriptor of the
+/// standard error they replaced
+static mut CAPTURES: uint = 0;
+static mut SAVED_STDERR: std::libc::c_int = -1;
+/// Permissions of the capture file: read and write for the owner only
+static CAPTURE_MODE: std::libc::c_int = std::libc::S_IRUSR | std::libc::S_IWUSR;
+
+fn task_name() -> ~str {
+    fmt!("qc isolate %u", unsafe { TASKS.fetch_add(1, SeqCst) })
+}
+
+/**
+ Run `f` with the standard error of the process written to a temporary file,
+ and return its result and the text written. The text is written to standard
+ error when the last running capture ends, so that nothing is lost.
+
+ The runtime only logs the message of a task failure, so this is how the
+ message is found. Captures that overlap share the file, and the text each
+ returns includes what the others captured, so find a message by the name of
+ its task. Standard error is redirected only while a capture runs, and the
+ lock is only held to start and end one, not while `f` runs.
+ */
+fn capture_stderr<T>(f: &fn() -> T) -> (T, ~str) {
+    let path = capture_path();
+    if !begin_capture(&path) {
+        return (f(), ~"");
+    }
+    let x = f();
+    let text = read_capture(&path);
+    end_capture(&path);
+    (x, text)
+}
+
+/// The temporary file standard error is captured in
+fn capture_path() -> Path {
+    std::os::tmpdir().push(fmt!("qc-stderr-%d", unsafe { std::libc::getpid() } as int))
+}
+
+/// The text captured in `path` so far
+fn read_capture(path: &Path) -> ~str {
+    match std::io::read_whole_file_str(path) {
+        Ok(text) => text,
+        Err(_) => ~"",
+    }
+}
+
+/// Run `f` holding the capture lock
+fn with_capture_lock<T>(f: &fn() -> T) -> T {
+    unsafe {
+        while CAPTURE_LOCK.swap(true, SeqCst) {
+            std::task::yield();
+        }
+    }
+    let x = f();
+    unsafe { CAPTURE_LOCK.store(false, SeqCst); }
+    x
+}
+
+/// Start a capture, redirecting standard error to `path` unless a running
+/// capture already did. Return false if the file could not be opened.
+fn begin_capture(path: &Path) -> bool {
+    use std::libc;
+    do with_capture_lock {
+        unsafe {
+            if CAPTURES == 0 {
+                let fd = do path.to_str().with_c_str |p| {
+                    libc::open(p, libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC, CAPTURE_MODE)
+                };
+                if fd >= 0 {
+                    SAVED_STDERR = libc::dup(2);
+                    libc::dup2(fd, 2);
+                    libc::close(fd);
+                    CAPTURES = 1;
+                    true
+                } else {
+                    false
+                }
+            } else {
+                CAPTURES += 1;
+                true
+            }
+        }
+    }
+}
+
+/// End a capture, and if it was the last one running, restore standard error
+/// and write the text captured in `path` to it
+fn end_capture(path: &Path) {
+    use std::libc;
+    use std::io::WriterUtil;
+    do with_capture_lock {
+        unsafe {
+            CAPTURES -= 1;
+            if CAPTURES == 0 {
+                libc::dup2(SAVED_STDERR, 2);
+                libc::close(SAVED_STDERR);
+                SAVED_STDERR = -1;
+                std::io::stderr().write_str(read_capture(path));
+                std::os::remove_file(path);
+            }
+        }
+    }
+}
+
+/// Return the message of the failure of the task `name` logged in `text`, if any.
+/// The runtime logs it as `task '<name>' failed at '<message>', <file>:<line>`.
+fn failure_message(text: &str, name: &str) -> Option<~str> {
+    let prefix = fmt!("task '%s' failed at '", name);
+    for line in text.split_iter('\n') {
+        match line.find_str(prefix) {
+            Some(i) => {
+                let rest = line.slice_from(i + prefix.len());
+                match rest.rfind('\'') {
+                    Some(j) => return Some(rest.slice_to(j).to_owned()),
+                    None => {}
+                }
             }
-            let shrink = quick_shrink(cfg, v_copy, prop);
-            fail!(fmt!("qc %s: falsified (%u trials) with value '%?'", name, 1+i, shrink));
+            None => {}
         }
+    }
+    None
+}
+
+/**
+ Shrink `value`, a counterexample to `prop`, to a minimal counterexample.
+
+ A shrunk value is accepted when `prop` returns a failing `TestResult` for it.
+
+ `prop` is called directly, so it must not fail its task; use `isolate` in
+ the property for properties that may fail.
+
+ With `deep` configured, a value none of whose shrinks fail is shrunk further
+ with `shrink_deep`, and then by trying each shrink of each of its shrinks,
+ until none of those fail either. With `restarts` configured, shrinking is
+ run again from `value` with the shrinks in shuffled orders.
+
+ Shrinking stops early at the limits configured with `max_shrinks`,
+ `max_shrink_evals` and `shrink_time`, returning the smallest value found.
+ */
+pub fn quick_shrink<A: Clone + Shrink, R: Testable>(cfg: QConfig, value: A,
+                                                    prop: &fn(A) -> R) -> A {
+    let (shrunk, _) = quick_shrink_stats(cfg, value, prop);
+    shrunk
+}
+
+/// Shrink `value` like `quick_shrink`, and also return the statistics of
+/// shrinking it
+pub fn quick_shrink_stats<A: Clone + Shrink, R: Testable>(cfg: QConfig, value: A,
+                                                          prop: &fn(A) -> R) -> (A, ShrinkStats) {
+    let (seed, _) = cfg.run_seed();
+    let Shrunk{node, stats, _} =
+        shrink_count(cfg, value, Fail(~""), false, seed, |v| v.shrink(), |v| v.shrink_deep(),
+                     |v| (prop(v).result(), false), |v| v.clone());
+    (node, stats)
+}
+
+/**
+ Shrink `node` like `quick_shrink`, using `shrink` and then `deep` for its
+ shrinks, also returning the failure of the shrunk node, the limit that
+ stopped shrinking if any, statistics and the trace of shrink steps if
+ configured. `value` gives the value of a node for verbose output.
+
+ `test` also returns true if the test timed out, and so does `hung` for
+ `node`; shrinking stops once `max_timeouts` nodes have timed out. `seed`
+ shuffles the shrinks for the configured `restarts`.
+ */
+fn shrink_count<N: Clone, A, I: Iterator<N>, J: Iterator<N>>(
+        cfg: QConfig, node: N, res: TestResult, hung: bool, seed: uint,
+        shrink: &fn(&N) -> I,
+        deep: &fn(&N) -> J,
+        test: &fn(N) -> (TestResult, bool),
+        value: &fn(&N) -> A) -> Shrunk<N> {
+    let start = extra::time::precise_time_ns();
+    let s = Shrunk{node: node, res: res, limit: None,
+                   stats: ShrinkStats{evaluated: 0, steps: 0, depth: 0,
+                                      timed_out: if hung { 1 } else { 0 }, time_ns: 0},
+                   trace: ~[], tried: 0, start: start};
+    shrink_from(cfg, s, seed, shrink, deep, test, value)
+}
+
+/// Continue shrinking `s` like `shrink_count`, restarting from `s` with the
+/// shrinks shuffled for the configured `restarts`
+fn shrink_from<N: Clone, A, I: Iterator<N>, J: Iterator<N>>(
+        cfg: QConfig, s: Shrunk<N>, seed: uint,
+        shrink: &fn(&N) -> I,
+        deep: &fn(&N) -> J,
+        test: &fn(N) -> (TestResult, bool),
+        value: &fn(&N) -> A) -> Shrunk<N> {
+    let mut best = s.clone();
+    while best.limit.is_none() && best.shrink_once(cfg, shrink, deep, test, value) {}
+    let mut i = 0;
+    while i < cfg.restarts && best.limit.is_none() {
         i += 1;
+        let mut g = Source::for_trial(seed, i, 0);
+        let mut r = s.clone();
+        r.stats.evaluated = best.stats.evaluated;
+        r.stats.timed_out = best.stats.timed_out;
+        while r.limit.is_none() && r.shrink_once(cfg, |n| shuffled(&mut g, shrink(n)),
+                                                 deep, test, value) {}
+        if cfg.verbose {
+            println(fmt!("Shrink restart %u: %?", i, &value(&r.node)));
+        }
+        if r.stats.depth > best.stats.depth {
+            best = r;
+        } else {
+            best.stats.evaluated = r.stats.evaluated;
+            best.stats.timed_out = r.stats.timed_out;
+            best.limit = r.limit;
+        }
     }
+    best.stats.time_ns = extra::time::precise_time_ns() - best.start;
     if cfg.verbose {
-        println(fmt!("qc %s: passed'", name));
+        match best.limit {
+            Some(l) => println(fmt!("Shrink stopped by %s: %?", l.to_str(), &value(&best.node))),
+            None => println(fmt!("Shrink finished: %?", &value(&best.node))),
+        }
+        println(fmt!("Shrink stats: %s", best.stats.to_str()));
     }
+    best
 }
 
-pub fn quick_shrink<A: Clone + Shrink>(cfg: QConfig, value: A, prop: &fn(A) -> bool) -> A {
-    for elt in value.shrink() {
-        let elt_cpy = elt.clone();
-        if !prop(elt) {
-            if cfg.verbose { println(fmt!("Shrunk to: %?", &elt_cpy)); }
-            return quick_shrink(cfg, elt_cpy, prop);
+/// The items of `it` in an order shuffled with `g`
+fn shuffled<N, I: Iterator<N>>(g: &mut Source, it: I) -> std::vec::MoveIterator<N> {
+    let mut v = it.collect::<~[N]>();
+    g.shuffle_mut(v);
+    v.move_iter()
+}
+
+impl<N: Clone> Shrunk<N> {
+    /// Take one shrink step, trying the deeper strategies only if the simpler
+    /// ones fail. Return true if a smaller counterexample was found.
+    fn shrink_once<A, I: Iterator<N>, J: Iterator<N>>(
+            &mut self, cfg: QConfig,
+            shrink: &fn(&N) -> I,
+            deep: &fn(&N) -> J,
+            test: &fn(N) -> (TestResult, bool),
+            value: &fn(&N) -> A) -> bool {
+        let shrinks = shrink(&self.node);
+        if self.step(cfg, shrinks, 1, test, value) {
+            return true;
+        }
+        if !cfg.deep || self.limit.is_some() {
+            return false;
         }
+        let shrinks = deep(&self.node);
+        if self.step(cfg, shrinks, 1, test, value) {
+            return true;
+        }
+        /* two coordinated shrinks; the first alone is known to pass */
+        let stop = self.stats.evaluated + PAIR_CANDIDATES;
+        for elt in shrink(&self.node) {
+            let left = stop - self.stats.evaluated;
+            if self.step(cfg, shrink(&elt).take(left), 2, test, value) {
+                return true;
+            }
+            if self.limit.is_some() || self.stats.evaluated >= stop {
+                break;
+            }
+        }
+        false
     }
-    if cfg.verbose {
-        println(fmt!("Shrink finished: %?", &value));
+
+    /// Test `shrinks` in order, and make the first one that fails the new node,
+    /// `depth` single shrinks from the old one. Return true if one failed.
+    fn step<A, I: Iterator<N>>(&mut self, cfg: QConfig, shrinks: I, depth: uint,
+               test: &fn(N) -> (TestResult, bool), value: &fn(&N) -> A) -> bool {
+        for elt in shrinks {
+            self.limit = cfg.shrink_limit(&self.stats, self.start);
+            if self.limit.is_some() {
+                return false;
+            }
+            let elt_cpy = elt.clone();
+            let (elt_res, hung) = test(elt);
+            self.stats.evaluated += 1;
+            self.tried += 1;
+            if hung {
+                self.stats.timed_out += 1;
+            }
+            if elt_res.is_failure() {
+                if cfg.verbose { println(fmt!("Shrunk to: %?", &value(&elt_cpy))); }
+                if cfg.trace {
+                    self.trace.push(ShrinkStep{value: elt_cpy.clone(), candidates: self.tried,
+                                               reason: elt_res.reason()});
+                }
+                self.node = elt_cpy;
+                self.res = elt_res;
+                self.stats.steps += 1;
+                self.stats.depth += depth;
+                self.tried = 0;
+                return true;
+            }
+        }
+        false
     }
-    value
 }
 
-pub fn quick_check_occurs<A: Arbitrary>(cfg: QConfig, name: &str, prop: &fn(A) -> bool) {
-    let mut n = 0u;
-    for i in range(0, cfg.trials) {
-        n += 1;
-        let value = arbitrary(cfg.size + if cfg.grow { i / 8 } else { 0 });
-        if prop(value) {
+pub fn quick_check_occurs<A: Arbitrary, R: Testable>(cfg: QConfig, name: &str,
+                                                     prop: &fn(A) -> R) {
+    let (seed, replay) = cfg.run_seed();
+    let (first, last) = match replay {
+        Some(t) => (t, t + 1),
+        None => (0, cfg.trials),
+    };
+    let mut found = false;
+    for i in range(first, last) {
+        let mut g = cfg.trial_source(seed, i);
+        let value = arbitrary(&mut g);
+        if prop(value).result() == Pass {
             if cfg.verbose {
-                println(fmt!("qc %s: occured (%u trials)", name, n));
+                println(fmt!("qc %s: occured (%u trials)", name, i - first + 1));
             }
+            found = true;
             break;
         }
     }
-    if n >= cfg.trials {
-        fail!(fmt!("qc %s: could not to reproduce", name));
+    if !found && first == last {
+        fail!(fmt!("qc %s: could not reproduce (no trials)", name));
+    } else if !found {
+        fail!(fmt!("qc %s: could not reproduce (seed %u, trials %u to %u)",
+                   name, seed, first, last - 1));
     }
 }
 
-pub macro_rules! quick_check(
+pub macro_rules! quick_check(
+    ($qc_property:expr) => (
+        quick_check!(config, $qc_property)
+    );
+    ($qc_config:expr, $qc_property:expr) => ({
+        quick_check(
+            fmt!("%s\n%s:%u", stringify!($qc_property), file!(), line!()),
+            $qc_config,
+            $qc_property);
+    })
+)
+
+pub macro_rules! for_all(
+    ($qc_gen:expr, $qc_property:expr) => (
+        for_all!(config, $qc_gen, $qc_property)
+    );
+    ($qc_config:expr, $qc_gen:expr, $qc_property:expr) => ({
+        for_all(
+            fmt!("%s\n%s:%u", stringify!($qc_property), file!(), line!()),
+            $qc_config,
+            &$qc_gen,
+            $qc_property);
+    })
+)
+
+pub macro_rules! for_all_shrink(
+    ($qc_gen:expr, $qc_shrink:expr, $qc_property:expr) => (
+        for_all_shrink!(config, $qc_gen, $qc_shrink, $qc_property)
+    );
+    ($qc_config:expr, $qc_gen:expr, $qc_shrink:expr, $qc_property:expr) => ({
+        for_all_shrink(
+            fmt!("%s\n%s:%u", stringify!($qc_property), file!(), line!()),
+            $qc_config,
+            $qc_gen,
+            $qc_shrink,
+            $qc_property);
+    })
+)
+
+pub macro_rules! quick_check_isolated(
+    ($qc_property:expr) => (
+        quick_check_isolated!(config, $qc_property)
+    );
+    ($qc_config:expr, $qc_property:expr) => ({
+        quick_check_isolated(
+            fmt!("%s\n%s:%u", stringify!($qc_property), file!(), line!()),
+            $qc_config,
+            $qc_property);
+    })
+)
+
+pub macro_rules! quick_check_choices(
+    ($qc_property:expr) => (
+        quick_check_choices!(config, $qc_property)
+    );
+    ($qc_config:expr, $qc_property:expr) => ({
+        quick_check_choices(
+            fmt!("%s\n%s:%u", stringify!($qc_property), file!(), line!()),
+            $qc_config,
+            $qc_property);
+    })
+)
+
+pub macro_rules! quick_check_slice(
     ($qc_property:expr) => (
-        quick_check!(config, $qc_property)
+        quick_check_slice!(config, $qc_property)
     );
     ($qc_config:expr, $qc_property:expr) => ({
-        quick_check(
+        quick_check_slice(
+            fmt!("%s\n%s:%u", stringify!($qc_property), file!(), line!()),
+            $qc_config,
+            $qc_property);
+    })
+)
+
+pub macro_rules! quick_check_str(
+    ($qc_property:expr) => (
+        quick_check_str!(config, $qc_property)
+    );
+    ($qc_config:expr, $qc_property:expr) => ({
+        quick_check_str(
+            fmt!("%s\n%s:%u", stringify!($qc_property), file!(), line!()),
+            $qc_config,
+            $qc_property);
+    })
+)
+
+pub macro_rules! quick_check_array(
+    ($qc_len:expr, $qc_property:expr) => (
+        quick_check_array!(config, $qc_len, $qc_property)
+    );
+    ($qc_config:expr, $qc_len:expr, $qc_property:expr) => ({
+        quick_check_array(
             fmt!("%s\n%s:%u", stringify!($qc_property), file!(), line!()),
             $qc_config,
+            $qc_len,
             $qc_property);
     })
 )
@@ -175,6 +1320,174 @@ pub macro_rules! quick_check_occurs(
     })
 )
 
+/**
+ Implement Arbitrary for a struct, by listing its fields.
+
+ Requires `Arbitrary` and `Source` in scope.
+
+ Example:
+
+ `arbitrary_struct!(Point { x, y })`
+ */
+pub macro_rules! arbitrary_struct(
+    ($S:ident { $($f:ident),+ }) => (
+        impl Arbitrary for $S {
+            fn arbitrary(g: &mut Source) -> $S {
+                $S { $($f: Arbitrary::arbitrary(g)),+ }
+            }
+        }
+    )
+)
+
+/**
+ Implement Shrink for a struct, by listing its fields.
+ Fields are shrunk using the Shrink impl for tuples, with both `shrink` and
+ `shrink_deep`.
+
+ Requires `Shrink` and `Lazy` in scope, and the struct to implement `Clone`.
+
+ Example:
+
+ `shrink_struct!(Point { x, y })`
+ */
+pub macro_rules! shrink_struct(
+    ($S:ident { $($f:ident),+ }) => (
+        impl Shrink for $S {
+            fn shrink(&self) -> Lazy<$S> {
+                do Lazy::create |L| {
+                    match self.clone() {
+                        $S { $($f),+ } => {
+                            L.push_map(($($f),+).shrink(), |($($f),+)| $S { $($f: $f),+ });
+                        }
+                    }
+                }
+            }
+
+            fn shrink_deep(&self) -> Lazy<$S> {
+                do Lazy::create |L| {
+                    match self.clone() {
+                        $S { $($f),+ } => {
+                            L.push_map(($($f),+).shrink_deep(), |($($f),+)| $S { $($f: $f),+ });
+                        }
+                    }
+                }
+            }
+        }
+    )
+)
+
+/**
+ Implement Arbitrary for an enum, by listing its variants.
+
+ Variants without fields are listed first, then a `;`, then the variants with
+ fields that do not contain the enum, then a `;`, then the recursive variants,
+ whose fields contain the enum. The fields of each variant are named.
+
+ The smaller the size factor, the more likely a variant that is not recursive
+ is chosen, and the fields of recursive variants are generated with half the
+ size factor, so that recursive enums stay finite.
+
+ Requires `Arbitrary` and `Source` in scope.
+
+ Example:
+
+ `arbitrary_enum!(Expr { Zero, One; Lit(n); Add(a, b), Neg(a) })`
+ */
+pub macro_rules! arbitrary_enum(
+    ($E:ident { $($Leaf:ident),* ; $($P:ident($($p:ident),+)),* ;
+                $($V:ident($($f:ident),+)),* }) => (
+        impl Arbitrary for $E {
+            fn arbitrary(g: &mut Source) -> $E {
+                let leaves: &[&'static str] = [$(stringify!($Leaf)),*];
+                let plain: &[&'static str] = [$(stringify!($P)),*];
+                let nodes: &[&'static str] = [$(stringify!($V)),*];
+                let base = leaves.len() + plain.len();
+                let sz = g.size();
+                let mut k = if base > 0 && (nodes.len() == 0 || g.gen_index(sz + 1) == 0) {
+                    g.gen_index(base)
+                } else {
+                    g.gen_index(base + nodes.len())
+                };
+                $(
+                    if k == 0 { return $Leaf; }
+                    k -= 1;
+                )*
+                $(
+                    if k == 0 {
+                        return $P($({ stringify!($p); Arbitrary::arbitrary(g) }),+);
+                    }
+                    k -= 1;
+                )*
+                $(
+                    if k == 0 {
+                        return g.with_size(sz / 2, |g| {
+                            $V($({ stringify!($f); Arbitrary::arbitrary(g) }),+)
+                        });
+                    }
+                    k -= 1;
+                )*
+                fail!()
+            }
+        }
+    )
+)
+
+/**
+ Implement Shrink for an enum, by listing its variants like for
+ `arbitrary_enum!`.
+
+ A variant with fields shrinks first to each of the variants without fields,
+ then its fields are shrunk using the Shrink impl for tuples, which also gives
+ their deep shrinks.
+
+ Requires `Shrink` and `Lazy` in scope, and the enum to implement `Clone`.
+
+ Example:
+
+ `shrink_enum!(Expr { Zero, One; Lit(n); Add(a, b), Neg(a) })`
+ */
+pub macro_rules! shrink_enum(
+    ($E:ident { $($Leaf:ident),* ; $($P:ident($($p:ident),+)),* ;
+                $($V:ident($($f:ident),+)),* }) => (
+        impl Shrink for $E {
+            fn shrink(&self) -> Lazy<$E> {
+                let leaves: ~[$E] = ~[$($Leaf),*];
+                do Lazy::create |L| {
+                    match self.clone() {
+                        $($Leaf => {})*
+                        $($P($($p),+) => {
+                            for x in leaves.iter() {
+                                L.push(x.clone());
+                            }
+                            L.push_map(($($p),+).shrink(), |($($p),+)| $P($($p),+));
+                        })*
+                        $($V($($f),+) => {
+                            for x in leaves.iter() {
+                                L.push(x.clone());
+                            }
+                            L.push_map(($($f),+).shrink(), |($($f),+)| $V($($f),+));
+                        })*
+                    }
+                }
+            }
+
+            fn shrink_deep(&self) -> Lazy<$E> {
+                do Lazy::create |L| {
+                    match self.clone() {
+                        $($Leaf => {})*
+                        $($P($($p),+) => {
+                            L.push_map(($($p),+).shrink_deep(), |($($p),+)| $P($($p),+));
+                        })*
+                        $($V($($f),+) => {
+                            L.push_map(($($f),+).shrink_deep(), |($($f),+)| $V($($f),+));
+                        })*
+                    }
+                }
+            }
+        }
+    )
+)
+
 impl Shrink for SmallN {
     fn shrink(&self) -> Lazy<SmallN> {
         do Lazy::create |L| {
@@ -191,12 +1504,15 @@ enum UserTree<T> {
 }
 
 impl<T: Clone + Arbitrary> Arbitrary for UserTree<T> {
-    fn arbitrary(sz: uint) -> UserTree<T> {
-        let rint: u8 = std::rand::random();
+    fn arbitrary(g: &mut Source) -> UserTree<T> {
+        let sz = g.size();
+        let rint: u8 = g.gen();
         if sz == 0 || rint % 4 == 0 {
             Nil
         } else {
-            Node(arbitrary(sz), ~arbitrary(sz/2), ~arbitrary(sz/2))
+            Node(arbitrary(g),
+                 g.with_size(sz/2, |g| ~arbitrary(g)),
+                 g.with_size(sz/2, |g| ~arbitrary(g)))
         }
     }
 }
@@ -216,6 +1532,37 @@ impl<T: Send + Clone + Shrink> Shrink for UserTree<T> {
     }
 }
 
+/// Example of using the macros to implement Arbitrary and Shrink
+#[deriving(Clone, Eq)]
+enum UserExpr {
+    Zero,
+    One,
+    Add(~UserExpr, ~UserExpr),
+    Neg(~UserExpr),
+    Lit(u8),
+}
+
+arbitrary_enum!(UserExpr { Zero, One; Lit(x); Add(a, b), Neg(a) })
+shrink_enum!(UserExpr { Zero, One; Lit(x); Add(a, b), Neg(a) })
+
+#[deriving(Clone, Eq)]
+struct UserPoint {
+    x: uint,
+    name: ~str,
+}
+
+arbitrary_struct!(UserPoint { x, name })
+shrink_struct!(UserPoint { x, name })
+
+/// Example of a type without a Shrink impl
+#[deriving(Clone, Eq)]
+struct UserArea {
+    w: uint,
+    h: uint,
+}
+
+arbitrary_struct!(UserArea { w, h })
+
 
 #[test]
 fn test_qc_basic() {
@@ -226,6 +1573,11 @@ fn test_qc_basic() {
     let mut m = 0;
     quick_check_occurs!(|_: int| { m += 1; m == 20 });
     assert_eq!(m, 20);
+
+    /* Found in the last trial, and in a single replayed trial */
+    let mut k = 0;
+    quick_check_occurs!(config.trials(5), |_: int| { k += 1; k == 5 });
+    quick_check_occurs!(config.replay(7, 3), |_: int| true);
 }
 
 #[test]
@@ -240,6 +1592,303 @@ fn test_qc_occurs_fail() {
     quick_check_occurs!(|s: ~str| s.len() == -1);
 }
 
+#[test]
+#[should_fail]
+fn test_qc_occurs_no_trials() {
+    quick_check_occurs!(config.trials(0), |_: int| true);
+}
+
+#[test]
+fn test_qc_check() {
+    match check("pass", config.trials(10), |_: int| true) {
+        Passed(n) => assert_eq!(n, 10),
+        _ => fail!(),
+    }
+
+    match check("len", config.seed(3), |v: ~[SmallN]| v.len() < 3) {
+        Falsified(c) => {
+            assert!(c.original.len() >= 3);
+            assert_eq!(c.shrunk, ~[SmallN(0), SmallN(0), SmallN(0)]);
+            assert!(c.shrink.steps > 0 || c.original == c.shrunk);
+            assert_eq!(c.seed, 3);
+            assert_eq!(c.trials, c.trial + 1);
+        }
+        _ => fail!(),
+    }
+}
+
+#[test]
+fn test_qc_testable() {
+    quick_check!(|_: int| ());
+    quick_check!(|x: u8| if x < 255 { Pass } else { Discard });
+
+    match check("reason", config, |x: uint| if x < 5 { Ok(()) } else { Err(fmt!("%u is big", x)) }) {
+        Falsified(c) => {
+            assert_eq!(c.shrunk, 5);
+            assert_eq!(c.reason, ~"5 is big");
+        }
+        _ => fail!(),
+    }
+
+    let s = quick_shrink(config, ~[1u8, 2, 3], |v| if v.len() < 2 { Pass } else { Fail(~"long") });
+    assert_eq!(s.len(), 2);
+}
+
+#[test]
+#[should_fail]
+fn test_qc_testable_fail() {
+    quick_check!(|v: ~[int]| if v.len() < 3 { Ok(()) } else { Err(~"too long") });
+}
+
+#[test]
+fn test_qc_discard() {
+    /* Only accepted values count as trials */
+    let mut n = 0;
+    quick_check!(|v: ~[u8]| implies(v.len() >= 2, || { n += 1; v[0] != v[1] || v[0] == v[1] }));
+    assert_eq!(n, config.trials);
+
+    match check("discard", config.trials(10).max_discard(3), |_: int| Discard) {
+        GaveUp(0, d) => assert_eq!(d, 31),
+        _ => fail!(),
+    }
+}
+
+#[test]
+#[should_fail]
+fn test_qc_gave_up() {
+    quick_check!(|v: ~[u8]| implies(v.len() > 1000, || false));
+}
+
+#[test]
+fn test_qc_task_failure() {
+    match check_isolated("fail", config, |x: uint| { if x > 10 { fail!("too big") } true }) {
+        Falsified(c) => {
+            assert_eq!(c.shrunk, 11);
+            assert_eq!(c.reason, ~"too big");
+        }
+        _ => fail!(),
+    }
+
+    let prop = Isolated::new(|v: ~[uint]| v[1] == 2);
+    let shrink = quick_shrink(config, ~[1u, 2, 3], |v| isolate(&prop, v));
+    assert_eq!(shrink, ~[]);
+    assert!(isolate(&prop, ~[]).reason().contains("index out of bounds"));
+
+    quick_check_isolated!(|v: ~[int]| v.len() < 1000);
+}
+
+#[test]
+fn test_qc_not_send() {
+    /* Values that can't be sent to other tasks are tested in the current task */
+    quick_check!(|x: @int| *x == *x);
+    quick_check!(|v: @mut ~[u8]| v.len() == v.len());
+    let mut n = 0;
+    quick_check!(|a: @mut bool| { n += 1; *a || !*a });
+    assert_eq!(n, config.trials);
+}
+
+#[test]
+fn test_qc_timeout() {
+    fn busy_wait(ms: u64) {
+        let end = extra::time::precise_time_ns() + ms * 1000000;
+        while extra::time::precise_time_ns() < end {}
+    }
+    /* small values without boundaries, shrunk only with `Shrink` */
+    let cfg = config.timeout(50).size(2).grow(false).boundary(0).choices(false);
+    match check_isolated("timeout", cfg, |x: uint| { if x > 10 { busy_wait(200) } true }) {
+        Falsified(c) => {
+            /* shrinking goes on past values that time out */
+            assert_eq!(c.shrunk, 11);
+            assert!(c.shrink.timed_out > 0);
+            assert_eq!(c.shrink_limit, None);
+            assert_eq!(c.reason, ~"timed out after 50 ms");
+        }
+        _ => fail!(),
+    }
+
+    match check_isolated("timeout", cfg.max_timeouts(1),
+                         |x: uint| { if x > 10 { busy_wait(200) } true }) {
+        Falsified(c) => {
+            assert_eq!(c.shrunk, c.original);
+            assert_eq!(c.shrink.evaluated, 0);
+            assert_eq!(c.shrink.timed_out, 1);
+            assert_eq!(c.shrink_limit, Some(TimedOut(1)));
+        }
+        _ => fail!(),
+    }
+
+    /* values that time out are smaller counterexamples than failing ones */
+    match check_isolated("timeout", cfg, |x: uint| {
+        if x > 15 { fail!() } else if x > 10 { busy_wait(200) } true
+    }) {
+        Falsified(c) => {
+            assert_eq!(c.shrunk, 11);
+            assert_eq!(c.shrink_limit, None);
+            assert_eq!(c.reason, ~"timed out after 50 ms");
+        }
+        _ => fail!(),
+    }
+
+    quick_check_isolated!(config.timeout(1000), |_: ~[int]| true);
+}
+
+#[test]
+fn test_qc_for_all() {
+    let mut n = 0;
+    for_all!(gen::choose(1, 6), |x: int| { n += 1; x >= 1 && x <= 6 });
+    assert_eq!(n, config.trials);
+
+    let evens = gen::vec_of(gen::choose(0u, 100).map(|x| 2 * x));
+    for_all!(config.trials(100), evens, |v: ~[uint]| v.iter().all(|x| x % 2 == 0));
+}
+
+#[test]
+fn test_qc_for_all_shrink_gen() {
+    /* Shrinks keep the constraints of the generator */
+    let evens = gen::choose(0u, 1000).map(|x| 2 * x);
+    match check_rose("evens", config, |g| evens.generate_rose(g), |x: uint| x < 100) {
+        Falsified(c) => assert_eq!(c.shrunk, 100),
+        _ => fail!(),
+    }
+
+    let odds = gen::choose(1, 99).such_that(|x| *x % 2 == 1);
+    match check_rose("odds", config, |g| odds.generate_rose(g), |x: int| x < 10) {
+        Falsified(c) => assert!(c.shrunk % 2 == 1 && c.shrunk > 10),
+        _ => fail!(),
+    }
+
+    let bounded = gen::choose(1u, 10).bind(|n| gen::vec_of(gen::choose(0, n)));
+    match check_rose("bounded", config, |g| bounded.generate_rose(g),
+                     |v: ~[uint]| v.iter().all(|&x| x < 5)) {
+        Falsified(c) => assert_eq!(c.shrunk, ~[5]),
+        _ => fail!(),
+    }
+
+    let letters = gen::vec_of(gen::elements(&['x', 'y', 'z']));
+    match check_rose("letters", config, |g| letters.generate_rose(g),
+                     |v: ~[char]| v.len() < 3) {
+        Falsified(c) => assert_eq!(c.shrunk, ~['x', 'x', 'x']),
+        _ => fail!(),
+    }
+}
+
+#[test]
+#[should_fail]
+fn test_qc_for_all_fail() {
+    for_all!(gen::elements(&[~"a", ~"b", ~"c"]), |s: ~str| s != ~"c");
+}
+
+#[test]
+fn test_qc_for_all_shrink() {
+    use extra::complex::Cmplx;
+
+    /* Cmplx implements neither Arbitrary nor Shrink */
+    match check_with("cmplx", config,
+            |g| Cmplx::new(*arbitrary::<SmallN>(g), *arbitrary::<SmallN>(g)),
+            |c| {
+                let mut v = ~[];
+                for re in c.re.shrink() { v.push(Cmplx::new(re, c.im)); }
+                for im in c.im.shrink() { v.push(Cmplx::new(c.re, im)); }
+                Lazy::new_from(v)
+            },
+            |c: Cmplx<uint>| c.re + c.im < 10) {
+        Falsified(c) => assert_eq!(c.shrunk.re + c.shrunk.im, 10),
+        _ => fail!(),
+    }
+
+    for_all_shrink!(|g| Cmplx::new(arbitrary::<int>(g), 0), |_| Lazy::new(),
+                    |c: Cmplx<int>| c.im == 0);
+}
+
+#[test]
+fn test_qc_regex() {
+    /* Shrinks still match */
+    let re = Regex::new("[a-z]+@[a-z]+\\.(com|org)");
+    match check_with("email", config, |g| re.generate(g), |s| re.shrink(s),
+                     |s: ~str| s.len() < 10) {
+        Falsified(c) => {
+            assert!(re.is_match(c.shrunk));
+            assert_eq!(c.shrunk.len(), 10);
+        }
+        _ => fail!(),
+    }
+
+    for_all!(gen::regex("\\d{4}-\\d\\d-\\d\\d"), |s: ~str| s.len() == 10);
+}
+
+#[test]
+fn test_qc_grammar() {
+    let expr = Grammar::new("expr")
+        .rule("expr", 2, "num")
+        .rule("expr", 1, "expr ' + ' expr")
+        .rule("expr", 1, "'(' expr ')'")
+        .rule("num", 1, "/[1-9][0-9]*/");
+
+    /* Shrinking keeps the text derivable */
+    match check_with("parens", config, |g| expr.generate(g), |d| expr.shrink(d),
+                     |d: Derivation| !d.to_str().contains("(")) {
+        Falsified(c) => assert_eq!(c.shrunk.to_str(), ~"(1)"),
+        _ => fail!(),
+    }
+
+    /* The size factor is the depth budget */
+    for_all!(config.size(0).grow(false), gen::grammar(&expr),
+             |s: ~str| s.iter().all(|c| c.is_digit()));
+    for_all!(config.size(3).grow(false), gen::grammar(&expr), |s: ~str| !s.contains("(("));
+}
+
+#[test]
+fn test_qc_tuples() {
+    quick_check!(|(a, b, c, d, e, f, g): (u8, u8, u8, u8, u8, u8, u8)|
+                 a as uint + b as uint + c as uint + d as uint + e as uint + f as uint + g as uint
+                     <= 7 * 255);
+    quick_check_occurs!(|(_, _, _, _, _, _, _, x): (int, int, int, int, int, int, int, Option<int>)|
+                        x.is_some());
+}
+
+#[test]
+fn test_qc_slices() {
+    quick_check_slice!(|v: &[int]| v.iter().max() == v.iter().invert().max());
+    quick_check_str!(|s: &str| s.char_len() <= s.len());
+    quick_check_array!(4, |v: &[u8]| v.len() == 4);
+    quick_check_array!(config.trials(10), 32, |v: &[~str]| v.len() == 32);
+
+    quick_check_occurs!(|a: [u8, ..4]| a[0] != a[3]);
+    quick_check_occurs!(|a: [Option<int>, ..16]| a[15].is_some());
+    let mut shrinks = [5u, 3, 9].shrink();
+    assert_eq!(shrinks.next().map(|a| a.to_owned()), Some(~[0u, 3, 9]));
+    assert!(shrinks.all(|a| a[0] + a[1] + a[2] < 17));
+}
+
+#[test]
+#[should_fail]
+fn test_qc_array_fail() {
+    quick_check_array!(3, |v: &[uint]| v[1] < 10);
+}
+
+#[test]
+fn test_qc_derive() {
+    quick_check_occurs!(|e: UserExpr| e == Zero);
+    quick_check_occurs!(|e: UserExpr| match e { Add(~Neg(*), _) => true, _ => false });
+    quick_check_occurs!(config.size(0).grow(false), |e: UserExpr| e == One);
+    /* Lit is not recursive, so it is chosen at any size */
+    quick_check_occurs!(config.size(0).grow(false), |e: UserExpr| e == Lit(0));
+    quick_check!(config.size(0).grow(false), |e: UserExpr| match e {
+        Add(*) | Neg(*) => false,
+        _ => true,
+    });
+
+    let e = Add(~Neg(~Lit(7)), ~Add(~One, ~Lit(3)));
+    assert_eq!(quick_shrink(config, e.clone(), |_| false), Zero);
+    assert_eq!(quick_shrink(config, e, |e| match e { Add(*) => false, _ => true }),
+               Add(~Zero, ~Zero));
+
+    quick_check_occurs!(|p: UserPoint| p.x > 0 && p.name.len() > 0);
+    let p = UserPoint{x: 100, name: ~"name"};
+    assert_eq!(quick_shrink(config, p, |p| p.x < 3 || p.name.len() < 1),
+               UserPoint{x: 3, name: ~"a"});
+}
+
 #[test]
 fn test_qc_func() {
     let mut n = 0;
@@ -260,6 +1909,29 @@ fn test_qc_config() {
 }
 
 
+#[test]
+fn test_qc_seed() {
+    /* The same seed must produce the same values */
+    let mut g = Source::new(1729, 8);
+    let mut h = Source::new(1729, 8);
+    for _ in range(0, 20) {
+        let x: (~[int], ~str, Option<u8>) = arbitrary(&mut g);
+        let y: (~[int], ~str, Option<u8>) = arbitrary(&mut h);
+        assert_eq!(x, y);
+    }
+
+    let mut xs = ~[];
+    quick_check!(config.seed(42), |x: ~[u8]| { xs.push(x); true });
+    let mut ys = ~[];
+    quick_check!(config.seed(42), |y: ~[u8]| { ys.push(y); true });
+    assert_eq!(xs, ys);
+
+    /* Replaying a trial reproduces exactly that trial's value */
+    let mut zs = ~[];
+    quick_check!(config.replay(42, 17), |z: ~[u8]| { zs.push(z); true });
+    assert_eq!(zs, ~[xs[17].clone()]);
+}
+
 #[test]
 fn test_qc_smalln() {
     quick_check_occurs!(|n: SmallN| *n == 0);
@@ -321,6 +1993,21 @@ fn test_qc_shrink() {
     assert_eq!(quick_shrink(config, s, |v| !v.iter().any(|x| x.map_default(false, |s| s.contains_char('e')))),
         ~[Some(~"e")]);
 
+    /* Chars shrink toward 'a' */
+    assert_eq!(quick_shrink(config, 'Q', |_| false), 'a');
+    assert_eq!(quick_shrink(config, 'Q', |c| !c.is_uppercase()), 'A');
+    assert_eq!(quick_shrink(config, '\u00a7', |c| c.is_alphanumeric() || c == ' '), '.');
+    assert_eq!(quick_shrink(config, '7', |c| !c.is_digit()), '0');
+    assert_eq!(quick_shrink(config, 'a', |_| false), 'a');
+
+    /* Strings shrink their chars too */
+    let s = ~"\u00e9\u200b\U0001f600 XYZ";
+    assert_eq!(quick_shrink(config, s, |_| false), ~"");
+    let s = ~"\u00e9\u200b\U0001f600 XYZ";
+    assert_eq!(quick_shrink(config, s, |s| s.char_len() < 3), ~"aaa");
+    let s = ~"Hello, W\u00f6rld";
+    assert_eq!(quick_shrink(config, s, |s| s.is_ascii()), ~"\u00f6");
+
     let s = ~"boots are made for walking";
     assert_eq!(quick_shrink(config, s, |v| v.iter().count(|x| x == 'a') <= 1),
         ~"aa");
@@ -338,14 +2025,98 @@ fn test_qc_shrink() {
     let shrink = quick_shrink(config, s, |(a, b, c)| *a + *b + *c == 0);
     assert_eq!(shrink, (SmallN(0), SmallN(0), SmallN(1)));
 
-    /* test the biggest supported tuple */
-    let t: (uint, (), ~[u8], Option<bool>, u8, ~str) = arbitrary(config.size);
+    let t: (uint, (), ~[u8], Option<bool>, u8, ~str) = arbitrary(&mut Source::new(0, config.size));
     let shrink = quick_shrink(config, t, |_| false);
     assert_eq!(shrink, (0, (), ~[], None, 0, ~""));
+
+    /* test the biggest supported tuple */
+    let t: (uint, (), ~[u8], Option<bool>, u8, ~str, i64, char, SmallN, f64, (int, int), u16) =
+        arbitrary(&mut Source::new(0, config.size));
+    let shrink = quick_shrink(config, t, |_| false);
+    assert_eq!(shrink, (0, (), ~[], None, 0, ~"", 0, 'a', SmallN(0), 0.0, (0, 0), 0));
+
+    let t = (1u, 2u, 3u, 4u, 5u, 6u, 7u, 8u);
+    let shrink = quick_shrink(config, t, |(a, b, c, d, e, f, g, h)| a + b + c + d + e + f + g + h < 8);
+    assert_eq!(shrink, (0, 0, 0, 0, 0, 0, 0, 8));
+}
+
+#[test]
+fn test_qc_shrink_numbers() {
+    assert_eq!(quick_shrink(config, -1000, |x| x > -10), -10);
+    assert_eq!(quick_shrink(config, 12345i64, |x| x < 100), 100);
+    assert_eq!(quick_shrink(config, -128i8, |_| false), 0);
+    assert_eq!(quick_shrink(config, -77i16, |x| x == 0), 1);
+    assert_eq!(quick_shrink(config, 60000u16, |x| x < 300), 300);
+    assert_eq!(quick_shrink(config, 4000000000u32, |_| false), 0);
+    assert_eq!(quick_shrink(config, -12.5f64, |x| x > -1.0), -1.0);
+    assert_eq!(quick_shrink(config, 3.25f32, |x| x == 0.0), 1.0);
+
+    /* Every width reaches its extremes through the boundary values */
+    quick_check_occurs!(config.boundary(100), |x: i16| x < -1000);
+    quick_check_occurs!(config.boundary(100), |x: i32| x > 1000000);
+    quick_check_occurs!(config.boundary(100), |x: i64| x < -1000000000000);
+    quick_check_occurs!(config.boundary(100), |x: u64| x > 1000000000000);
+    quick_check!(|x: u16| x as uint <= 65535);
+    quick_check!(|(x, y): (i32, u32)| x as i64 + y as i64 == y as i64 + x as i64);
+    quick_check_occurs!(|x: f32| x > 0.5);
+    quick_check_occurs!(|x: f64| x < 0.5);
+}
+
+#[test]
+fn test_qc_numbers() {
+    /* Magnitude scales with size */
+    quick_check!(config.size(4).grow(false).boundary(0), |x: int| x >= -64 && x <= 64);
+    quick_check_occurs!(config.size(1000).boundary(0), |x: uint| x > 1000);
+    quick_check_occurs!(config.boundary(0), |x: i8| x < 0);
+
+    /* Boundary values */
+    quick_check_occurs!(config.trials(1000), |x: int| x == std::int::max_value);
+    quick_check_occurs!(config.boundary(100), |x: i32| x == std::i32::min_value);
+    quick_check_occurs!(config.boundary(100), |x: u8| x == 255);
+    quick_check_occurs!(config.boundary(100).trials(500), |x: u16| x == 1024);
+    quick_check_occurs!(config.boundary(100), |x: i64| x == -1);
+    quick_check!(config.boundary(100), |x: u32| x == 0 || x == 1 || x == 0xffffffff ||
+                                                x == 0xfffffffe || x & (x - 1) == 0 ||
+                                                x & (x + 1) == 0);
+}
+
+#[test]
+fn test_qc_floats() {
+    let nan = 0.0f64 / 0.0;
+    let is_subnormal = |x: f64| x != 0.0 && x.abs() < 2.2250738585072014e-308;
+
+    quick_check_occurs!(config.boundary(100), |x: f64| x.is_NaN());
+    quick_check_occurs!(config.boundary(100), |x: f64| x.is_infinite() && x > 0.0);
+    quick_check_occurs!(config.boundary(100), |x: f64| x.is_infinite() && x < 0.0);
+    quick_check_occurs!(config.boundary(100), |x: f64| x == 0.0 && x.is_negative());
+    quick_check_occurs!(config.boundary(100), |x: f64| is_subnormal(x));
+    quick_check_occurs!(config.boundary(100), |x: f32| x.is_NaN());
+    quick_check_occurs!(|x: f64| x < -1e100);
+    quick_check_occurs!(|x: f64| x.abs() < 1e-100 && x != 0.0);
+    quick_check_occurs!(|x: f64| x > 1.0 && x < 100.0);
+    quick_check_occurs!(|x: f32| x < -1e30);
+    quick_check_occurs!(|x: float| x < 0.0);
+
+    assert_eq!(quick_shrink(config, 3.14159f64, |x| x < 3.0), 3.0);
+    assert_eq!(quick_shrink(config, 0.123456f64, |x| x < 0.1), 0.1);
+    assert_eq!(quick_shrink(config, -2.71828f64, |x| x > -2.7), -2.7);
+    assert_eq!(quick_shrink(config, 2.71828f64, |x| x < 2.7), 2.7);
+    assert_eq!(quick_shrink(config, 1e300f64, |x| x < 1000.0), 1000.0);
+    assert!(quick_shrink(config, nan, |x| !x.is_NaN()).is_NaN());
+    let z = quick_shrink(config, -0.0f64, |_| false);
+    assert!(z == 0.0 && !z.is_negative());
+    assert_eq!(quick_shrink(config, nan, |_| false), 0.0);
 }
 
 #[test]
 fn test_qc_shrink_containers() {
+    use std::hashmap::HashSet;
+    use extra::treemap::{TreeMap, TreeSet};
+    use extra::ringbuf::RingBuf;
+    use extra::dlist::DList;
+    use extra::priority_queue::PriorityQueue;
+    use extra::smallintmap::SmallIntMap;
+
     let shrink: Either<~str, int> = quick_shrink(config, Left(~"xyz"), |_| false);
     assert_eq!(shrink, Left(~""));
     let shrink: Either<int, ~str> = quick_shrink(config, Right(~"xyz"), |_| false);
@@ -360,6 +2131,45 @@ fn test_qc_shrink_containers() {
 
     let shrink = quick_shrink(config, std::cell::Cell::new((@mut 1, ~[1,2,3])),  |x| x.is_empty());
     assert_eq!(shrink, std::cell::Cell::new((@mut 1, ~[])));
+
+    /* Collections shrink like ~[T] */
+    let s: HashSet<int> = [5, -7, 30, 2].iter().map(|&x| x).collect();
+    let shrink = quick_shrink(config, s, |s| s.len() < 2);
+    assert_eq!(shrink.len(), 2);
+    assert!(shrink.contains(&0) && shrink.contains(&1));
+
+    let m: TreeMap<uint, ~str> = [(5u, ~"x"), (9, ~"yz"), (20, ~"!")].iter()
+        .map(|&(k, ref v)| (k, v.clone())).collect();
+    let shrink = quick_shrink(config, m, |m| m.iter().all(|(_, v)| v.len() < 2));
+    assert_eq!(shrink.iter().map(|(&k, v)| (k, v.clone())).collect::<~[(uint, ~str)]>(),
+               ~[(0, ~"aa")]);
+
+    let s: TreeSet<int> = [3, 8, 13].iter().map(|&x| x).collect();
+    let shrink = quick_shrink(config, s, |s| s.iter().all(|&x| x < 10));
+    assert_eq!(shrink.iter().map(|&x| x).collect::<~[int]>(), ~[10]);
+
+    let b: RingBuf<uint> = range(0u, 10).collect();
+    let shrink = quick_shrink(config, b, |b| b.iter().all(|&x| x < 5));
+    assert_eq!(shrink.iter().map(|&x| x).collect::<~[uint]>(), ~[5]);
+
+    let l: DList<~str> = [~"ab", ~"c"].iter().map(|x| x.clone()).collect();
+    let shrink = quick_shrink(config, l, |l| l.len() < 2);
+    assert_eq!(shrink.iter().map(|x| x.clone()).collect::<~[~str]>(), ~[~"", ~""]);
+
+    let q = PriorityQueue::from_vec(~[3, 1, 4, 1, 5]);
+    let shrink = quick_shrink(config, q, |q| q.iter().all(|&x| x != 5));
+    assert_eq!(shrink.to_vec(), ~[5]);
+
+    let b = extra::bitv::from_bools([true, false, true, true]);
+    let shrink = quick_shrink(config, b, |b| b.to_bools().iter().count(|&x| x) < 2);
+    assert_eq!(shrink.to_bools(), ~[true, true]);
+
+    let mut m = SmallIntMap::new();
+    m.insert(3, 7u);
+    m.insert(40, 2);
+    let shrink = quick_shrink(config, m, |m| m.len() < 2);
+    assert_eq!(shrink.iter().map(|(k, &v)| (k, v)).collect::<~[(uint, uint)]>(),
+               ~[(0, 0), (1, 0)]);
 }
 
 #[test]
@@ -385,6 +2195,14 @@ struct Test_Foo { x: float, u: int }
 
 #[test]
 fn test_qc_containers() {
+    use std::hashmap::HashSet;
+    use extra::treemap::{TreeMap, TreeSet};
+    use extra::ringbuf::RingBuf;
+    use extra::dlist::DList;
+    use extra::priority_queue::PriorityQueue;
+    use extra::bitv::Bitv;
+    use extra::smallintmap::SmallIntMap;
+
     quick_check_occurs!(|s: Option<int>| s.is_none());
     quick_check_occurs!(|s: Option<int>| s.is_some());
 
@@ -393,7 +2211,8 @@ fn test_qc_containers() {
     quick_check_occurs!(|v: ~[u8]| v.len() > 10);
     quick_check_occurs!(config.size(100), |v: ~[u8]| v.len() > 100);
 
-    quick_check!(|s: ~str| s.is_ascii());
+    quick_check_occurs!(|s: ~str| s.is_ascii());
+    quick_check_occurs!(|s: ~str| !s.is_ascii());
 
     quick_check_occurs!(|s: Either<u8,u8>| match s { Left(*) => true, _ => false });
     quick_check_occurs!(|s: Either<u8,u8>| match s { Right(*) => true, _ => false });
@@ -406,6 +2225,20 @@ fn test_qc_containers() {
 
     quick_check_occurs!(|m: std::cell::Cell<~str>| m.is_empty());
     quick_check_occurs!(|m: std::cell::Cell<@mut int>| !m.is_empty());
+
+    quick_check_occurs!(|m: TreeMap<u8, ~str>| m.len() > 3);
+    quick_check_occurs!(|s: TreeSet<i8>| s.len() > 3);
+    quick_check_occurs!(|s: HashSet<char>| s.len() > 3);
+    quick_check_occurs!(|b: RingBuf<int>| b.len() > 3);
+    quick_check_occurs!(|l: DList<int>| l.len() > 3);
+    quick_check_occurs!(|q: PriorityQueue<uint>| q.len() > 3);
+    quick_check_occurs!(|b: Bitv| b.to_bools().iter().any(|&x| x));
+    quick_check_occurs!(|m: SmallIntMap<bool>| m.len() > 3);
+
+    quick_check!(|q: PriorityQueue<int>| {
+        let v = q.to_sorted_vec();
+        v.iter().zip(v.iter().skip(1)).all(|(a, b)| a <= b)
+    });
 }
 
 #[test]
@@ -415,6 +2248,20 @@ fn test_str() {
         std::str::is_utf8(ss.as_bytes())
     });
 
+    quick_check!(|c: char| c as u32 <= 0x10ffff && (c as u32 < 0xd800 || c as u32 > 0xdfff));
+    quick_check_occurs!(|c: char| c.is_ascii() && c >= ' ' && c < '\x7f');
+    quick_check_occurs!(|c: char| c as u32 >= 0x80 && c as u32 < 0x800);
+    quick_check_occurs!(|c: char| c as u32 >= 0x800 && c as u32 < 0x10000);
+    quick_check_occurs!(|c: char| c as u32 >= 0x10000);
+    quick_check_occurs!(config.trials(500), |c: char| c == '\u0301' || c == '\u0308');
+    quick_check_occurs!(config.trials(500), |c: char| c == '\u200b' || c == '\u200d');
+    quick_check_occurs!(config.trials(500), |c: char| c == '\u202e' || c == '\u200f');
+    quick_check_occurs!(config.trials(500), |c: char| c == '\ud7ff' || c == '\ue000');
+
+    /* Mostly ASCII */
+    let mut ascii = 0;
+    quick_check!(config.trials(1000), |c: char| { if c.is_ascii() { ascii += 1 } true });
+    assert!(ascii > 500);
 
 }
 
@@ -453,3 +2300,186 @@ fn test_random_stuff() {
             ys.len() == x.len() + y.len()
     });
 }
+
+#[test]
+fn test_qc_choices() {
+    use std::rand::Rng;
+    /* 0 to 1023, growing with the choice made */
+    fn small(g: &mut Source) -> uint { (g.next() >> 22) as uint }
+
+    /* UserArea has no Shrink impl */
+    quick_check_choices!(|a: UserArea| a.w * a.h == a.h * a.w);
+    match check_choices("area", config, |g| UserArea{w: small(g), h: small(g)},
+                        |a: UserArea| a.w < 10 || a.h < 20) {
+        Falsified(c) => assert_eq!((c.shrunk.w, c.shrunk.h), (10, 20)),
+        _ => fail!(),
+    }
+
+    /* Shrunk values are generated again from their choices */
+    match check_choices("even", config, |g| 2 * small(g), |x: uint| x < 10) {
+        Falsified(c) => assert_eq!(c.shrunk, 10),
+        _ => fail!(),
+    }
+
+    /* check shrinks through the choices, and then with Shrink */
+    match check("choices", config, |v: ~[u8]| v.len() < 3) {
+        Falsified(c) => assert_eq!(c.shrunk, ~[0, 0, 0]),
+        _ => fail!(),
+    }
+    match check("choices", config, |x: i64| x < 100) {
+        Falsified(c) => assert_eq!(c.shrunk, 100),
+        _ => fail!(),
+    }
+    match check("shrink", config.choices(false), |v: ~[u8]| v.len() < 3) {
+        Falsified(c) => assert_eq!(c.shrunk, ~[0, 0, 0]),
+        _ => fail!(),
+    }
+}
+
+/// Shrink a number only to the next smaller one, for long shrink runs
+#[cfg(test)]
+fn down(x: &uint) -> Lazy<uint> {
+    Lazy::new_from(if *x > 0 { ~[*x - 1] } else { ~[] })
+}
+
+#[test]
+fn test_qc_shrink_limits() {
+    /* 1000 shrinks to 0, which passes, then to 500 */
+    assert_eq!(quick_shrink(config.max_shrinks(1), 1000u, |x| x < 10), 500);
+    assert_eq!(quick_shrink(config.max_shrink_evals(1), 1000u, |x| x < 10), 1000);
+    assert_eq!(quick_shrink(config.shrink_time(0), 1000u, |x| x < 10), 1000);
+
+    /* Shrinking is a loop, so many steps don't overflow the stack */
+    match check_with("down", config, |_| 10000u, down, |x: uint| x == 0) {
+        Falsified(c) => {
+            assert_eq!(c.shrunk, 1);
+            assert_eq!(c.shrink.steps, 9999);
+            assert_eq!(c.shrink_limit, None);
+        }
+        _ => fail!(),
+    }
+    match check_with("down", config.max_shrinks(100), |_| 10000u, down, |x: uint| x == 0) {
+        Falsified(c) => {
+            assert_eq!(c.shrunk, 9900);
+            assert_eq!(c.shrink_limit, Some(StepLimit(100)));
+        }
+        _ => fail!(),
+    }
+    match check_with("down", config.max_shrink_evals(10), |_| 10000u, down, |x: uint| x == 0) {
+        Falsified(c) => {
+            assert_eq!(c.shrunk, 9990);
+            assert_eq!(c.shrink_limit, Some(EvalLimit(10)));
+        }
+        _ => fail!(),
+    }
+}
+
+#[test]
+#[should_fail]
+fn test_qc_shrink_limits_fail() {
+    quick_check!(config.max_shrinks(0), |v: ~[int]| v.len() < 5);
+}
+
+#[test]
+fn test_qc_shrink_stats() {
+    fn prop(x: uint) -> TestResult {
+        if x == 0 { Pass } else { Fail(fmt!("%u", x)) }
+    }
+    match check_with("down", config.trace(true), |_| 5u, down, prop) {
+        Falsified(c) => {
+            assert_eq!(c.shrunk, 1);
+            assert_eq!((c.shrink.steps, c.shrink.depth, c.shrink.evaluated), (4, 4, 5));
+            assert_eq!(c.trace.iter().map(|t| t.value).collect::<~[uint]>(), ~[4, 3, 2, 1]);
+            assert!(c.trace.iter().all(|t| t.candidates == 1 && t.reason == t.value.to_str()));
+        }
+        _ => fail!(),
+    }
+    let outcome = check_with("down", config.trace(true), |_| 5u, down, prop);
+    let msg = failure_report("down", outcome).unwrap();
+    let lines = msg.split_iter('\n').collect::<~[&str]>();
+    assert_eq!(lines.len(), 6);
+    assert!(lines[0].starts_with("qc down: falsified (1 trials) with value '1': 1"));
+    assert!(lines[1].starts_with("  shrinking: 4 steps to depth 4, 5 candidates evaluated in "));
+    assert_eq!(lines[2], "  shrink step 1: '4' after 1 candidates: 4");
+    assert_eq!(lines[5], "  shrink step 4: '1' after 1 candidates: 1");
+
+    match check_with("down", config, |_| 5u, down, prop) {
+        Falsified(c) => {
+            assert_eq!(c.shrink.steps, 4);
+            assert_eq!(c.trace.len(), 0);
+        }
+        _ => fail!(),
+    }
+    let outcome = check_with("down", config, |_| 5u, down, prop);
+    let msg = failure_report("down", outcome).unwrap();
+    assert_eq!(msg.split_iter('\n').collect::<~[&str]>().len(), 2);
+    assert_eq!(failure_report("pass", check("pass", config, |_: int| true)), None);
+
+    let (shrunk, stats) = quick_shrink_stats(config, 1000u, |x| x < 10);
+    assert_eq!(shrunk, 10);
+    assert!(stats.steps > 0 && stats.evaluated > stats.steps);
+    assert_eq!(stats.depth, stats.steps);
+}
+
+#[test]
+fn test_qc_shrink_deep() {
+    fn unequal((a, b): (uint, uint)) -> bool { a != b || a < 5 }
+    fn short(v: ~[int]) -> bool { v.len() % 3 != 1 }
+
+    /* No single shrink of (7, 7) fails, but shrinking both to 5 does */
+    assert_eq!(quick_shrink(config, (7u, 7u), unequal), (7, 7));
+    assert_eq!(quick_shrink(config.deep(true), (7u, 7u), unequal), (5, 5));
+
+    /* Removing one, two or half of four elements passes, but removing three fails */
+    assert_eq!(quick_shrink(config, ~[1, 2, 3, 4], short), ~[0, 0, 0, 0]);
+    assert_eq!(quick_shrink(config.deep(true), ~[1, 2, 3, 4], short), ~[0]);
+    assert_eq!(quick_shrink(config.deep(true), ~"abcd", |s: ~str| s.len() % 3 != 1), ~"a");
+
+    /* The sum of (1, 1, 1) is odd, and shrinking one element makes it even */
+    fn even((a, b, c): (SmallN, SmallN, SmallN)) -> bool { (*a + *b + *c) % 2 == 0 }
+    let s = (SmallN(1), SmallN(1), SmallN(1));
+    assert_eq!(quick_shrink(config, s.clone(), even), s.clone());
+    let (shrunk, stats) = quick_shrink_stats(config.deep(true), s.clone(), even);
+    assert_eq!(shrunk, (SmallN(0), SmallN(0), SmallN(1)));
+    assert_eq!(stats.depth, 2);
+
+    /* The search of shrinks of shrinks is capped: here it would try about
+       3700, after about 500 single and deep shrinks */
+    fn ones(v: ~[u8]) -> bool { v.len() != 30 || v.iter().any(|&x| x == 0) }
+    let v = std::vec::from_elem(30, 1u8);
+    let (shrunk, stats) = quick_shrink_stats(config.deep(true), v.clone(), ones);
+    assert_eq!(shrunk, v);
+    assert!(stats.evaluated > PAIR_CANDIDATES && stats.evaluated < 2 * PAIR_CANDIDATES);
+
+    assert_eq!(~[1, 2, 3, 4].shrink_deep().collect::<~[~[int]]>(),
+               ~[~[4], ~[1], ~[3, 4], ~[1, 4], ~[1, 2]]);
+
+    /* Tuples, structs and enums forward shrink_deep to their fields */
+    assert_eq!((~[1, 2, 3, 4], 7u).shrink_deep().collect::<~[(~[int], uint)]>(),
+               ~[(~[4], 7), (~[1], 7), (~[3, 4], 7), (~[1, 4], 7), (~[1, 2], 7)]);
+    let p = UserPoint{x: 1, name: ~"abc"};
+    assert_eq!(p.shrink_deep().map(|p| p.name).collect::<~[~str]>(), ~[~"c", ~"a"]);
+    assert_eq!(Some(~[1, 2, 3]).shrink_deep().collect::<~[Option<~[int]>]>(),
+               ~[Some(~[3]), Some(~[1])]);
+    assert_eq!(quick_shrink(config.deep(true), (~[1, 2, 3, 4], 7u), |(v, _)| short(v)),
+               (~[0], 0));
+}
+
+#[test]
+fn test_qc_shrink_restarts() {
+    /* The first failing shrink of (5, 5) is the dead end (2, 5), but the
+       other one, (5, 4), leads on to (2, 0) */
+    fn path(x: (uint, uint)) -> bool {
+        ![(5u, 5u), (2, 5), (5, 4), (5, 1), (5, 0), (2, 0)].contains(&x)
+    }
+    assert_eq!(quick_shrink(config, (5u, 5u), path), (2, 5));
+    let cfg = config.seed(1).restarts(20);
+    let (shrunk, stats) = quick_shrink_stats(cfg, (5u, 5u), path);
+    assert_eq!(shrunk, (2, 0));
+    assert_eq!(stats.depth, 4);
+
+    /* the same seed shuffles the same way */
+    let (again, stats_again) = quick_shrink_stats(cfg, (5u, 5u), path);
+    assert_eq!(again, shrunk);
+    assert_eq!(stats_again.evaluated, stats.evaluated);
+}
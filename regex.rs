@@ -0,0 +1,495 @@
+// vim: sts=4 sw=4 et
+
+/*!
+ Regex generates strings that match a regular expression, and shrinks them
+ only to smaller strings that still match.
+
+ The supported syntax is a small subset: literal characters, `.`, character
+ classes like `[a-z_]` and `[^0-9]`, the escapes `\d`, `\w` and `\s` and their
+ negations `\D`, `\W` and `\S`, groups `(...)`, alternation `|`, and the
+ repetitions `*`, `+`, `?`, `{n}`, `{n,}` and `{n,m}`.
+
+ A pattern always matches the whole string. `^` and `$` are accepted at the
+ start and end of the pattern but have no effect. Unbounded repetitions are
+ limited by the size factor.
+
+ Example::
+
+    let ident = Regex::new("[a-z_][a-z0-9_]*");
+    for_all_shrink!(|g| ident.generate(g), |s| ident.shrink(s),
+                    |s: ~str| lex(s).len() == 1);
+
+ */
+
+use super::std;
+use super::std::rand::RngUtil;
+
+use lazy::Lazy;
+use shrink::Shrink;
+use arbitrary::{Arbitrary, Source};
+
+/// Number of tries to generate a char outside of a negated class
+static NEGATED_TRIES: uint = 100;
+
+/// A parsed regular expression
+#[deriving(Clone)]
+pub struct Regex {
+    priv pattern: ~str,
+    priv node: Node,
+}
+
+#[deriving(Clone)]
+enum Node {
+    /// A single char
+    Lit(char),
+    /// Any char except newline
+    Any,
+    /// Inclusive char ranges, and whether the class is negated
+    Class(~[(char, char)], bool),
+    Cat(~[Node]),
+    Alt(~[Node]),
+    /// Repeat at least min and at most max times
+    Repeat(~Node, uint, Option<uint>),
+}
+
+impl Regex {
+    /// Parse `pattern`, or return a message describing why it is invalid
+    pub fn parse(pattern: &str) -> Result<Regex, ~str> {
+        let mut p = Parser{chars: pattern.iter().collect(), pos: 0};
+        match p.parse_alt() {
+            Err(e) => Err(e),
+            Ok(_) if p.pos < p.chars.len() => p.error("unmatched ')'"),
+            Ok(node) => Ok(Regex{pattern: pattern.to_owned(), node: node}),
+        }
+    }
+
+    /// Parse `pattern`, failing if it is invalid
+    pub fn new(pattern: &str) -> Regex {
+        match Regex::parse(pattern) {
+            Ok(re) => re,
+            Err(e) => fail!(fmt!("invalid regex '%s': %s", pattern, e)),
+        }
+    }
+
+    /// Return the pattern the regex was parsed from
+    pub fn pattern<'a>(&'a self) -> &'a str {
+        self.pattern.as_slice()
+    }
+
+    /// Generate a string matching the regex
+    pub fn generate(&self, g: &mut Source) -> ~str {
+        let mut s = ~"";
+        generate(&self.node, g, &mut s);
+        s
+    }
+
+    /// Return true if the whole of `s` matches the regex
+    pub fn is_match(&self, s: &str) -> bool {
+        let chars: ~[char] = s.iter().collect();
+        match_node(&self.node, chars, 0, |i| i == chars.len())
+    }
+
+    /// Shrink `s` like any `~str`, keeping only the shrinks that match the regex
+    pub fn shrink(&self, s: &~str) -> Lazy<~str> {
+        let mut L = Lazy::new();
+        push_matching(&mut L, s.shrink(), self.clone());
+        L
+    }
+}
+
+struct Parser {
+    chars: ~[char],
+    pos: uint,
+}
+
+impl Parser {
+    fn error<T>(&self, msg: &str) -> Result<T, ~str> {
+        Err(fmt!("%s at position %u", msg, self.pos))
+    }
+
+    fn peek(&self) -> Option<char> {
+        if self.pos < self.chars.len() { Some(self.chars[self.pos]) } else { None }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alt(&mut self) -> Result<Node, ~str> {
+        let mut alts = ~[];
+        loop {
+            match self.parse_cat() {
+                Ok(n) => alts.push(n),
+                Err(e) => return Err(e),
+            }
+            if !self.eat('|') {
+                break;
+            }
+        }
+        if alts.len() == 1 { Ok(alts.pop()) } else { Ok(Alt(alts)) }
+    }
+
+    fn parse_cat(&mut self) -> Result<Node, ~str> {
+        let mut nodes = ~[];
+        loop {
+            match self.peek() {
+                None | Some('|') | Some(')') => break,
+                _ => match self.parse_repeat() {
+                    Ok(n) => nodes.push(n),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        if nodes.len() == 1 { Ok(nodes.pop()) } else { Ok(Cat(nodes)) }
+    }
+
+    fn parse_repeat(&mut self) -> Result<Node, ~str> {
+        let mut node = match self.parse_atom() {
+            Ok(n) => n,
+            Err(e) => return Err(e),
+        };
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => { self.pos += 1; (0, None) }
+                Some('+') => { self.pos += 1; (1, None) }
+                Some('?') => { self.pos += 1; (0, Some(1)) }
+                Some('{') => {
+                    self.pos += 1;
+                    match self.parse_bounds() {
+                        Ok(b) => b,
+                        Err(e) => return Err(e),
+                    }
+                }
+                _ => break,
+            };
+            node = Repeat(~node, min, max);
+        }
+        Ok(node)
+    }
+
+    /// Parse the rest of `{n}`, `{n,}` or `{n,m}`
+    fn parse_bounds(&mut self) -> Result<(uint, Option<uint>), ~str> {
+        let min = match self.parse_number() {
+            Ok(n) => n,
+            Err(e) => return Err(e),
+        };
+        let max = if self.eat(',') {
+            if self.peek() == Some('}') {
+                None
+            } else {
+                match self.parse_number() {
+                    Ok(n) if n >= min => Some(n),
+                    Ok(_) => return self.error("invalid repetition bounds"),
+                    Err(e) => return Err(e),
+                }
+            }
+        } else {
+            Some(min)
+        };
+        if self.eat('}') { Ok((min, max)) } else { self.error("expected '}'") }
+    }
+
+    fn parse_number(&mut self) -> Result<uint, ~str> {
+        let start = self.pos;
+        let mut n = 0u;
+        loop {
+            match self.peek().chain(|c| std::char::to_digit(c, 10)) {
+                Some(d) => {
+                    n = n * 10 + d;
+                    self.pos += 1;
+                }
+                None => break,
+            }
+        }
+        if self.pos == start { self.error("expected a number") } else { Ok(n) }
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, ~str> {
+        let first = self.pos == 0;
+        match self.bump() {
+            None => self.error("unexpected end of pattern"),
+            Some('(') => {
+                if self.eat('?') && !self.eat(':') {
+                    return self.error("unsupported group");
+                }
+                let node = match self.parse_alt() {
+                    Ok(n) => n,
+                    Err(e) => return Err(e),
+                };
+                if self.eat(')') { Ok(node) } else { self.error("expected ')'") }
+            }
+            Some('[') => self.parse_class(),
+            Some('.') => Ok(Any),
+            Some('\\') => match self.bump() {
+                None => self.error("unexpected end of pattern"),
+                Some(e) => match class_escape(e) {
+                    Some((ranges, negated)) => Ok(Class(ranges, negated)),
+                    None => Ok(Lit(escape_char(e))),
+                }
+            },
+            Some('*') | Some('+') | Some('?') | Some('{') => self.error("nothing to repeat"),
+            Some('^') if first => Ok(Cat(~[])),
+            Some('$') if self.pos == self.chars.len() => Ok(Cat(~[])),
+            Some(c) => Ok(Lit(c)),
+        }
+    }
+
+    /// Parse the rest of a `[...]` class
+    fn parse_class(&mut self) -> Result<Node, ~str> {
+        let negated = self.eat('^');
+        let mut ranges = ~[];
+        /* a ']' right after the '[' is a literal */
+        let mut first = true;
+        loop {
+            let c = match self.bump() {
+                None => return self.error("expected ']'"),
+                Some(']') if !first => break,
+                Some(c) => c,
+            };
+            first = false;
+            let lo = if c == '\\' {
+                let e = match self.bump() {
+                    None => return self.error("unexpected end of pattern"),
+                    Some(e) => e,
+                };
+                match class_escape(e) {
+                    Some((rs, false)) => {
+                        ranges.push_all_move(rs);
+                        None
+                    }
+                    Some(_) => return self.error("negated escape in class"),
+                    None => Some(escape_char(e)),
+                }
+            } else {
+                Some(c)
+            };
+            match lo {
+                Some(lo) => match self.parse_range(lo) {
+                    Ok(r) => ranges.push(r),
+                    Err(e) => return Err(e),
+                },
+                None => {}
+            }
+        }
+        Ok(Class(ranges, negated))
+    }
+
+    /// Parse the end of a class range starting with `lo`, if any
+    fn parse_range(&mut self, lo: char) -> Result<(char, char), ~str> {
+        if self.peek() != Some('-') || self.pos + 1 >= self.chars.len()
+            || self.chars[self.pos + 1] == ']' {
+            return Ok((lo, lo));
+        }
+        self.pos += 1;
+        let hi = match self.bump() {
+            Some('\\') => match self.bump() {
+                Some(e) => escape_char(e),
+                None => return self.error("unexpected end of pattern"),
+            },
+            Some(c) => c,
+            None => return self.error("expected ']'"),
+        };
+        if hi < lo { self.error("invalid class range") } else { Ok((lo, hi)) }
+    }
+}
+
+/// Return the ranges of the class escape `\c`, and whether it is negated
+fn class_escape(c: char) -> Option<(~[(char, char)], bool)> {
+    let ranges = match c {
+        'd' | 'D' => ~[('0', '9')],
+        'w' | 'W' => ~[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')],
+        's' | 'S' => ~[(' ', ' '), ('\t', '\n'), ('\r', '\r')],
+        _ => return None,
+    };
+    Some((ranges, c.is_uppercase()))
+}
+
+/// Return the char for the literal escape `\c`
+fn escape_char(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        _ => c,
+    }
+}
+
+fn in_class(ranges: &[(char, char)], c: char) -> bool {
+    ranges.iter().any(|&(lo, hi)| c >= lo && c <= hi)
+}
+
+fn generate(node: &Node, g: &mut Source, s: &mut ~str) {
+    match *node {
+        Lit(c) => s.push_char(c),
+        Any => {
+            let mut c: char = Arbitrary::arbitrary(g);
+            while c == '\n' {
+                c = Arbitrary::arbitrary(g);
+            }
+            s.push_char(c)
+        }
+        Class(ref ranges, false) => s.push_char(choose_in(*ranges, g)),
+        Class(ref ranges, true) => s.push_char(choose_outside(*ranges, g)),
+        Cat(ref nodes) => {
+            for n in nodes.iter() {
+                generate(n, g, s);
+            }
+        }
+        Alt(ref nodes) => {
+            let i = g.gen_index(nodes.len());
+            generate(&nodes[i], g, s)
+        }
+        Repeat(ref n, min, max) => {
+            let max = match max {
+                Some(m) => m,
+                None => min + g.size(),
+            };
+            let count = g.gen_uint_range(min, max + 1);
+            for _ in range(0, count) {
+                generate(&**n, g, s);
+            }
+        }
+    }
+}
+
+/// Choose a char uniformly from `ranges`
+fn choose_in(ranges: &[(char, char)], g: &mut Source) -> char {
+    let total = ranges.iter().fold(0u, |a, &(lo, hi)| a + (hi as uint - lo as uint + 1));
+    let mut k = g.gen_index(total);
+    for &(lo, hi) in ranges.iter() {
+        let n = hi as uint - lo as uint + 1;
+        if k < n {
+            let c = lo as uint + k;
+            /* surrogates are not chars */
+            return if c >= 0xd800 && c <= 0xdfff { lo } else { c as u32 as char };
+        }
+        k -= n;
+    }
+    fail!()
+}
+
+/// Generate a char outside of `ranges`
+fn choose_outside(ranges: &[(char, char)], g: &mut Source) -> char {
+    for _ in range(0, NEGATED_TRIES) {
+        let c: char = Arbitrary::arbitrary(g);
+        if !in_class(ranges, c) {
+            return c;
+        }
+    }
+    fail!(fmt!("regex: no char outside of class after %u tries", NEGATED_TRIES))
+}
+
+/**
+ Match `node` against `s` starting at `i`, calling `k` with each position
+ where the match can end, until `k` returns true.
+
+ Return true if `k` returned true.
+ */
+fn match_node(node: &Node, s: &[char], i: uint, k: &fn(uint) -> bool) -> bool {
+    match *node {
+        Lit(c) => i < s.len() && s[i] == c && k(i + 1),
+        Any => i < s.len() && s[i] != '\n' && k(i + 1),
+        Class(ref ranges, negated) => {
+            i < s.len() && in_class(*ranges, s[i]) != negated && k(i + 1)
+        }
+        Cat(ref nodes) => match_seq(*nodes, s, i, k),
+        Alt(ref nodes) => nodes.iter().any(|n| match_node(n, s, i, |j| k(j))),
+        Repeat(ref n, min, max) => match_repeat(&**n, min, max, 0, s, i, k),
+    }
+}
+
+fn match_seq(nodes: &[Node], s: &[char], i: uint, k: &fn(uint) -> bool) -> bool {
+    if nodes.len() == 0 {
+        k(i)
+    } else {
+        match_node(&nodes[0], s, i, |j| match_seq(nodes.tail(), s, j, |m| k(m)))
+    }
+}
+
+/// Match `node` repeated, having already matched it `count` times; greedy
+fn match_repeat(node: &Node, min: uint, max: Option<uint>, count: uint,
+                s: &[char], i: uint, k: &fn(uint) -> bool) -> bool {
+    let more = match max {
+        Some(m) => count < m,
+        None => true,
+    };
+    /* once min is reached, a repetition matching nothing can't lead anywhere new */
+    more && match_node(node, s, i, |j| {
+        (j > i || count < min) && match_repeat(node, min, max, count + 1, s, j, |m| k(m))
+    }) || count >= min && k(i)
+}
+
+/// Lazily push the strings of `it` that match `re`
+fn push_matching(L: &mut Lazy<~str>, it: Lazy<~str>, re: Regex) {
+    do L.push_thunk((it, re)) |L, mut (it, re)| {
+        let mut found = None;
+        while found.is_none() {
+            match it.next() {
+                None => break,
+                Some(s) => if re.is_match(s) { found = Some(s) },
+            }
+        }
+        match found {
+            Some(s) => {
+                L.push(s);
+                push_matching(L, it, re);
+            }
+            None => {}
+        }
+    }
+}
+
+#[test]
+fn test_regex() {
+    let re = Regex::new("[a-z_][a-z0-9_]*");
+    assert!(re.is_match("x"));
+    assert!(re.is_match("_a1"));
+    assert!(!re.is_match(""));
+    assert!(!re.is_match("1a"));
+    assert!(!re.is_match("a-b"));
+
+    let re = Regex::new("^(ab|a)*b?c{2,3}$");
+    assert!(re.is_match("cc"));
+    assert!(re.is_match("aababccc"));
+    assert!(!re.is_match("abc"));
+    assert!(!re.is_match("ccccc"));
+
+    let re = Regex::new("\\d{4}-\\d\\d\\.[^\\s]+\\n?");
+    assert!(re.is_match("2013-09.x\n"));
+    assert!(!re.is_match("2013-09. "));
+    assert!(Regex::new("(|a)*").is_match(""));
+    assert!(Regex::new("[]a-]+").is_match("]-a"));
+
+    assert!(Regex::parse("(a").is_err());
+    assert!(Regex::parse("a)").is_err());
+    assert!(Regex::parse("*a").is_err());
+    assert!(Regex::parse("a{3,1}").is_err());
+    assert!(Regex::parse("[z-a]").is_err());
+    assert!(Regex::parse("[ab").is_err());
+
+    let mut g = Source::new(0, 8);
+    for pattern in ["[a-z_][a-z0-9_]*", "\\d{4}-\\d\\d-\\d\\d", "(a|bc)+[^x]?.",
+                    "[\\w.]+@[a-z]+\\.(com|org)", "\\S\\s\\W{0,3}"].iter() {
+        let re = Regex::new(*pattern);
+        for _ in range(0, 100) {
+            let s = re.generate(&mut g);
+            assert!(re.is_match(s));
+            for t in re.shrink(&s) {
+                assert!(re.is_match(t));
+            }
+        }
+    }
+}
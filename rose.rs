@@ -0,0 +1,268 @@
+// vim: sts=4 sw=4 et
+
+/*!
+ Rose is a value together with the tree of its shrinks, for integrated
+ shrinking.
+
+ A generator that builds the tree along with the value decides what the
+ shrinks are, so shrinking a mapped or filtered value shrinks the underlying
+ value and maps or filters again. A shrunk value is then always one the
+ generator could have produced.
+
+The children of a node are computed when they are first asked for, as
+ `Kids`, a sequence of trees whose own children are computed in turn. Each
+ child is only built when it is reached.
+ */
+
+use super::std;
+
+use lazy::Lazy;
+
+/// A value and the tree of its shrinks, the simplest first
+pub struct Rose<T> {
+    value: T,
+    priv kids: @fn() -> Kids<T>,
+}
+
+impl<T: Clone> Clone for Rose<T> {
+    fn clone(&self) -> Rose<T> {
+        Rose{value: self.value.clone(), kids: self.kids}
+    }
+}
+
+/**
+ The children of a tree, each built when it is reached.
+
+ Like `Lazy`, but trees are not sendable, so the parts that build them are
+ managed closures, called in order until each returns None.
+ */
+pub struct Kids<T> {
+    priv head: ~[Rose<T>],
+    priv parts: ~[@fn() -> Option<Rose<T>>],
+}
+
+impl<T> Kids<T> {
+    pub fn new() -> Kids<T> {
+        Kids::from_vec(~[])
+    }
+
+    pub fn from_vec(v: ~[Rose<T>]) -> Kids<T> {
+        Kids{head: v, parts: ~[]}
+    }
+
+    /// Append the trees `f` returns, until it returns None
+    pub fn push_fn(&mut self, f: @fn() -> Option<Rose<T>>) {
+        self.parts.push(f);
+    }
+}
+
+impl<T> Iterator<Rose<T>> for Kids<T> {
+    fn next(&mut self) -> Option<Rose<T>> {
+        if self.head.len() > 0 {
+            return Some(self.head.shift());
+        }
+        while self.parts.len() > 0 {
+            let f = self.parts[0];
+            match f() {
+                Some(r) => return Some(r),
+                None => { self.parts.shift(); }
+            }
+        }
+        None
+    }
+}
+
+impl<T: 'static> Rose<T> {
+    /// Create a tree from a value and a function computing its children
+    pub fn new(value: T, kids: @fn() -> Kids<T>) -> Rose<T> {
+        Rose{value: value, kids: kids}
+    }
+
+    /// Create a tree of a value that does not shrink
+    pub fn leaf(value: T) -> Rose<T> {
+        Rose::new(value, || Kids::new())
+    }
+
+    /// Return the trees of the shrinks of the value
+    pub fn children(&self) -> Kids<T> {
+        (self.kids)()
+    }
+
+    /// Apply `f` to the value and to all of its shrinks
+    pub fn map<U: 'static>(self, f: @fn(T) -> U) -> Rose<U> {
+        let Rose{value, kids} = self;
+        do Rose::new(f(value)) {
+            let it = @mut kids();
+            let mut K = Kids::new();
+            K.push_fn(|| it.next().map_move(|r| r.map(f)));
+            K
+        }
+    }
+
+    /// Remove the shrinks for which `p` is false, together with their own shrinks
+    pub fn filter(self, p: @fn(&T) -> bool) -> Rose<T> {
+        let Rose{value, kids} = self;
+        do Rose::new(value) {
+            let it = @mut kids();
+            let mut K = Kids::new();
+            do K.push_fn {
+                let mut next = None;
+                let mut done = false;
+                while !done {
+                    match it.next() {
+                        Some(r) => if p(&r.value) {
+                            next = Some(r.filter(p));
+                            done = true;
+                        },
+                        None => done = true,
+                    }
+                }
+                next
+            }
+            K
+        }
+    }
+
+    /**
+     Apply `f` to the value, giving a tree of the result.
+
+     The result shrinks first by shrinking the value and applying `f` again,
+     then like the tree given by `f`.
+     */
+    pub fn bind<U: 'static>(self, f: @fn(T) -> Rose<U>) -> Rose<U> {
+        let Rose{value, kids} = self;
+        let Rose{value: u, kids: ukids} = f(value);
+        do Rose::new(u) {
+            let it = @mut kids();
+            let uit = @mut ukids();
+            let mut K = Kids::new();
+            K.push_fn(|| it.next().map_move(|r| r.bind(f)));
+            K.push_fn(|| uit.next());
+            K
+        }
+    }
+}
+
+impl<T: 'static + Clone> Rose<T> {
+    /// Create a tree by shrinking `value` and each of its shrinks with `f`
+    pub fn unfold(value: T, f: @fn(&T) -> Lazy<T>) -> Rose<T> {
+        let x = value.clone();
+        do Rose::new(value) {
+            let it = @mut f(&x);
+            let mut K = Kids::new();
+            K.push_fn(|| it.next().map_move(|y| Rose::unfold(y, f)));
+            K
+        }
+    }
+
+    /**
+     Combine trees into a tree of the vector of their values.
+
+     It shrinks like `~[T]`: to the empty vector, to either half, and then for
+     each element, by removing it and by shrinking it like its own tree.
+     */
+    pub fn collect(roses: ~[Rose<T>]) -> Rose<~[T]> {
+        let value = roses.iter().map(|r| r.value.clone()).collect();
+        let roses = @roses;
+        do Rose::new(value) {
+            let n = roses.len();
+            let mut v = ~[];
+            if n > 0 {
+                v.push(Rose::collect(~[]));
+            }
+            if n > 2 {
+                v.push(Rose::collect(roses.slice(0, n / 2).to_owned()));
+                v.push(Rose::collect(roses.slice(n / 2, n).to_owned()));
+            }
+            let mut K = Kids::from_vec(v);
+            let it = @mut ElementShrinks{roses: roses, i: 0, kids: None};
+            K.push_fn(|| it.next());
+            K
+        }
+    }
+}
+
+/// The trees of a vector of trees with one tree removed or shrunk, for
+/// `Rose::collect`
+struct ElementShrinks<T> {
+    roses: @~[Rose<T>],
+    /* the tree at `i` is shrunk with `kids` after it was removed */
+    i: uint,
+    kids: Option<Kids<T>>,
+}
+
+impl<T: 'static + Clone> Iterator<Rose<~[T]>> for ElementShrinks<T> {
+    fn next(&mut self) -> Option<Rose<~[T]>> {
+        let roses = self.roses;
+        while self.i < roses.len() {
+            let i = self.i;
+            match self.kids {
+                None => {
+                    self.kids = Some(roses[i].children());
+                    let mut rs = std::vec::with_capacity(roses.len() - 1);
+                    rs.push_all(roses.slice(0, i));
+                    rs.push_all(roses.slice(i + 1, roses.len()));
+                    return Some(Rose::collect(rs));
+                }
+                Some(ref mut kids) => match kids.next() {
+                    Some(r) => {
+                        let mut rs = (*roses).clone();
+                        rs[i] = r;
+                        return Some(Rose::collect(rs));
+                    }
+                    None => {}
+                },
+            }
+            self.kids = None;
+            self.i += 1;
+        }
+        None
+    }
+}
+
+#[test]
+fn test_rose() {
+    fn halves(x: &uint) -> Lazy<uint> {
+        Lazy::new_from(if *x > 0 { ~[*x / 2] } else { ~[] })
+    }
+    fn path<T: 'static + Clone>(r: Rose<T>) -> ~[T] {
+        let mut v = ~[r.value.clone()];
+        let mut r = r;
+        loop {
+            match r.children().next() {
+                Some(c) => {
+                    v.push(c.value.clone());
+                    r = c;
+                }
+                None => break,
+            }
+        }
+        v
+    }
+
+    let r = Rose::unfold(12u, halves);
+    assert_eq!(path(r.clone()), ~[12, 6, 3, 1, 0]);
+    assert_eq!(path(r.clone().map(|x| x + 1)), ~[13, 7, 4, 2, 1]);
+    assert_eq!(path(r.clone().filter(|x| *x % 2 == 0)), ~[12, 6]);
+    assert_eq!(path(r.clone().bind(|x| Rose::leaf(x * 10))), ~[120, 60, 30, 10, 0]);
+    assert_eq!(path(Rose::leaf(5)), ~[5]);
+
+    let v = Rose::collect(~[Rose::unfold(4u, halves), Rose::leaf(1), Rose::unfold(2u, halves)]);
+    assert_eq!(v.value, ~[4, 1, 2]);
+    let kids: ~[~[uint]] = v.children().map(|r| r.value.clone()).collect();
+    assert_eq!(kids, ~[~[], ~[4], ~[1, 2], ~[1, 2], ~[2, 1, 2], ~[4, 2], ~[4, 1], ~[4, 1, 1]]);
+
+    /* Children are built as they are reached */
+    fn downs(x: &uint) -> Lazy<uint> {
+        Lazy::new_from(range(0, *x).collect())
+    }
+    let calls = @mut 0;
+    let r = Rose::unfold(100u, downs).map(|x| { *calls += 1; x });
+    assert_eq!(*calls, 1);
+    let mut kids = r.children();
+    assert_eq!(kids.next().map(|r| r.value), Some(0));
+    assert_eq!(*calls, 2);
+    let mut kids = r.filter(|x| *x % 2 == 1).children();
+    assert_eq!(kids.next().map(|r| r.value), Some(1));
+    assert_eq!(*calls, 4);
+}
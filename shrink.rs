@@ -4,7 +4,13 @@ use lazy::Lazy;
 use super::std;
 
 use std::cell::Cell;
-use std::hashmap::HashMap;
+use std::hashmap::{HashMap, HashSet};
+use extra::treemap::{TreeMap, TreeSet};
+use extra::ringbuf::RingBuf;
+use extra::dlist::DList;
+use extra::priority_queue::PriorityQueue;
+use extra::bitv::{Bitv, from_bools};
+use extra::smallintmap::SmallIntMap;
 
 /**
  The Shrink trait is used when trying to reduce a testcase to a minimal testcase.
@@ -14,14 +20,47 @@ pub trait Shrink {
     fn shrink(&self) -> Lazy<Self> {
         Lazy::new()
     }
+
+    /**
+     shrink_deep should generate simpler values that are too expensive to try
+     every time, for when none of the values from `shrink` is a counterexample
+     (with `QConfig::deep`).
+     */
+    fn shrink_deep(&self) -> Lazy<Self> {
+        Lazy::new()
+    }
 }
 
 impl Shrink for () {}
 impl Shrink for bool {}
-impl Shrink for char {}
-impl Shrink for float {}
-impl Shrink for i8 {}
-impl Shrink for int {}
+
+/// Order chars by simplicity: lowercase, uppercase, digits, space,
+/// other ASCII, then the rest
+fn char_rank(c: char) -> (uint, char) {
+    let class = if c >= 'a' && c <= 'z' { 0 }
+        else if c >= 'A' && c <= 'Z' { 1 }
+        else if c >= '0' && c <= '9' { 2 }
+        else if c == ' ' { 3 }
+        else if c > ' ' && c < '\x7f' { 4 }
+        else if c.is_ascii() { 5 }
+        else { 6 };
+    (class, c)
+}
+
+/// Shrink toward 'a', then other lowercase letters, digits, space and ASCII.
+/// Strings and other containers of chars use this to simplify each char.
+impl Shrink for char {
+    fn shrink(&self) -> Lazy<char> {
+        let c = *self;
+        let mut v = ~['a', 'b', 'c'];
+        if c >= 'A' && c <= 'Z' {
+            v.push((c as u8 - 'A' as u8 + 'a' as u8) as char);
+        }
+        v.push_all(['A', 'B', 'C', '0', '1', '2', ' ', '.', '\n']);
+        let rank = char_rank(c);
+        Lazy::new_from(v.move_iter().filter(|&x| char_rank(x) < rank).collect())
+    }
+}
 
 fn mpowers_of_two<T: Num + Ord>(n: T) -> ~[T] {
     /* generate ~[0, n/2, n - n/4, n - n/8, n - n/16, .., n - 1] */
@@ -57,67 +96,128 @@ impl Shrink for uint {
     fn shrink(&self) -> Lazy<uint> { Lazy::new_from(shrink_uint!(*self)) }
 }
 
-/* type out the (A, B) tuple case as we can save half the .clone() calls */
-impl<A: Send + Clone + Shrink, B: Send + Clone + Shrink> Shrink for (A, B) {
-    fn shrink(&self) -> Lazy<(A, B)> {
-        match self {
-            &(ref A, ref B) => {
-                let mut L = Lazy::new();
-                L.push_map_env(A.shrink(), B.clone(), |s, b| (s, b.clone()));
-                L.push_map_env(B.shrink(), A.clone(), |s, a| (a.clone(), s));
-                L
-            }
-        }
+impl Shrink for u16 {
+    fn shrink(&self) -> Lazy<u16> { Lazy::new_from(shrink_uint!(*self)) }
+}
+
+impl Shrink for u32 {
+    fn shrink(&self) -> Lazy<u32> { Lazy::new_from(shrink_uint!(*self)) }
+}
+
+impl Shrink for u64 {
+    fn shrink(&self) -> Lazy<u64> { Lazy::new_from(shrink_uint!(*self)) }
+}
+
+fn shrink_signed<T: Signed + Bounded + Ord + Clone>(n: T) -> ~[T] {
+    /* generate ~[0, -n, n - n/2, n - n/4, .., n - n/2^k] for n != 0,
+       -n only when n is negative and -n exists */
+    use std::num::{Zero, One, Bounded};
+    let zero: T = Zero::zero();
+    let one: T = One::one();
+    let two = one + one;
+    let mut ret = ~[];
+    if n == zero {
+        return ret;
+    }
+    ret.push(zero.clone());
+    let min: T = Bounded::min_value();
+    if n < zero && n != min {
+        ret.push(-n);
     }
+    let mut q = n / two;
+    while q != zero {
+        ret.push(n - q);
+        q = q / two;
+    }
+    ret
 }
 
-macro_rules! shrink_tuple(
-    ($($T:ident),+ -> $($S:expr),+) => (
-    impl<$($T: Send + Clone + Shrink),+> Shrink for ($($T),+) {
-        fn shrink(&self) -> Lazy<($($T),+)> {
-            do Lazy::create |L| {
-                match self {
-                    &($(ref $T),+) => {
-                        $(
-                            L.push_map_env($T.shrink(), self.clone(), |s, t| $S);
-                        )+
-                    }
-                }
+macro_rules! shrink_int( ($T:ty) => (
+        impl Shrink for $T {
+            fn shrink(&self) -> Lazy<$T> { Lazy::new_from(shrink_signed(*self)) }
+        }
+    )
+)
+
+shrink_int!(i8)
+shrink_int!(i16)
+shrink_int!(i32)
+shrink_int!(i64)
+shrink_int!(int)
+
+/// Cut `x` to `k` significant decimal digits, toward zero
+fn cut_digits(x: f64, k: int) -> f64 {
+    let e = x.abs().log10().floor();
+    let p = 10f64.pow(&((k - 1) as f64 - e));
+    (x * p).trunc() / p
+}
+
+fn shrink_float<T: Float + NumCast + Clone>(x: T) -> ~[T] {
+    /* NaN, inf and -0.0 shrink to 0.0 (and -inf to inf).
+       Integers shrink like integers, other numbers to
+       ~[0, -x, trunc(x), x cut to fewer significant digits..],
+       all closer to zero than x */
+    use std::num::{Zero, One};
+    let zero: T = Zero::zero();
+    let mut ret = ~[];
+    if x == zero {
+        if x.is_negative() {
+            ret.push(zero);
+        }
+        return ret;
+    }
+    if !x.is_finite() {
+        ret.push(zero.clone());
+        if x < zero {
+            ret.push(-x);
+        }
+        return ret;
+    }
+    let xf = x.to_f64();
+    if x.trunc() == x {
+        if xf.abs() < 9007199254740992.0 {
+            /* exactly representable as an integer */
+            for n in shrink_signed(xf as i64).move_iter() {
+                ret.push(NumCast::from(n));
+            }
+        } else {
+            let one: T = One::one();
+            let two = one + one;
+            ret.push(zero.clone());
+            if x < zero {
+                ret.push(-x);
             }
+            ret.push(x / two);
+        }
+        return ret;
+    }
+    ret.push(zero.clone());
+    if x < zero {
+        ret.push(-x);
+    }
+    ret.push(x.trunc());
+    for k in range(1, 18) {
+        let r: T = NumCast::from(cut_digits(xf, k));
+        if r == x {
+            break;
+        }
+        if r.is_finite() && r != x.trunc() {
+            ret.push(r);
         }
     }
+    ret
+}
+
+macro_rules! shrink_float( ($T:ty) => (
+        impl Shrink for $T {
+            fn shrink(&self) -> Lazy<$T> { Lazy::new_from(shrink_float(*self)) }
+        }
     )
 )
 
-shrink_tuple!(
-    A, B, C ->
-    (s,      t.n1(), t.n2()),
-    (t.n0(), s,      t.n2()),
-    (t.n0(), t.n1(), s))
-
-shrink_tuple!(
-    A, B, C, D ->
-    (s,      t.n1(), t.n2(), t.n3()),
-    (t.n0(), s,      t.n2(), t.n3()),
-    (t.n0(), t.n1(), s,      t.n3()),
-    (t.n0(), t.n1(), t.n2(), s))
-
-shrink_tuple!(
-    A, B, C, D, E ->
-    (s,      t.n1(), t.n2(), t.n3(), t.n4()),
-    (t.n0(), s,      t.n2(), t.n3(), t.n4()),
-    (t.n0(), t.n1(), s,      t.n3(), t.n4()),
-    (t.n0(), t.n1(), t.n2(), s,      t.n4()),
-    (t.n0(), t.n1(), t.n2(), t.n3(), s))
-
-shrink_tuple!(
-    A, B, C, D, E, F ->
-    (s,      t.n1(), t.n2(), t.n3(), t.n4(), t.n5()),
-    (t.n0(), s,      t.n2(), t.n3(), t.n4(), t.n5()),
-    (t.n0(), t.n1(), s,      t.n3(), t.n4(), t.n5()),
-    (t.n0(), t.n1(), t.n2(), s,      t.n4(), t.n5()),
-    (t.n0(), t.n1(), t.n2(), t.n3(), s,      t.n5()),
-    (t.n0(), t.n1(), t.n2(), t.n3(), t.n4(), s))
+shrink_float!(float)
+shrink_float!(f32)
+shrink_float!(f64)
 
 impl<T: Send + Clone + Shrink> Shrink for Option<T> {
     fn shrink(&self) -> Lazy<Option<T>> {
@@ -131,6 +231,15 @@ impl<T: Send + Clone + Shrink> Shrink for Option<T> {
             }
         }
     }
+
+    fn shrink_deep(&self) -> Lazy<Option<T>> {
+        do Lazy::create |L| {
+            match *self {
+                None => {}
+                Some(ref x) => L.push_map(x.shrink_deep(), |y| Some(y)),
+            }
+        }
+    }
 }
 
 impl<T: Send + Clone + Shrink, U: Send + Clone + Shrink> Shrink for Result<T, U> {
@@ -142,6 +251,15 @@ impl<T: Send + Clone + Shrink, U: Send + Clone + Shrink> Shrink for Result<T, U>
             }
         }
     }
+
+    fn shrink_deep(&self) -> Lazy<Result<T, U>> {
+        do Lazy::create |L| {
+            match *self {
+                Ok(ref x) => L.push_map(x.shrink_deep(), |y| Ok(y)),
+                Err(ref x) => L.push_map(x.shrink_deep(), |y| Err(y)),
+            }
+        }
+    }
 }
 
 impl<T: Send + Clone + Shrink, U: Send + Clone + Shrink> Shrink for Either<T, U> {
@@ -153,6 +271,15 @@ impl<T: Send + Clone + Shrink, U: Send + Clone + Shrink> Shrink for Either<T, U>
             }
         }
     }
+
+    fn shrink_deep(&self) -> Lazy<Either<T, U>> {
+        do Lazy::create |L| {
+            match *self {
+                Left(ref x) => L.push_map(x.shrink_deep(), |y| Left(y)),
+                Right(ref x) => L.push_map(x.shrink_deep(), |y| Right(y)),
+            }
+        }
+    }
 }
 
 impl<T: Send + Shrink> Shrink for ~T {
@@ -161,6 +288,12 @@ impl<T: Send + Shrink> Shrink for ~T {
             L.push_map((**self).shrink(), |u| ~u);
         }
     }
+
+    fn shrink_deep(&self) -> Lazy<~T> {
+        do Lazy::create |L| {
+            L.push_map((**self).shrink_deep(), |u| ~u);
+        }
+    }
 }
 
 impl<T: 'static + Send + Shrink> Shrink for @T {
@@ -169,6 +302,12 @@ impl<T: 'static + Send + Shrink> Shrink for @T {
             L.push_map((**self).shrink(), |u| @u);
         }
     }
+
+    fn shrink_deep(&self) -> Lazy<@T> {
+        do Lazy::create |L| {
+            L.push_map((**self).shrink_deep(), |u| @u);
+        }
+    }
 }
 
 impl<T: 'static + Send + Shrink> Shrink for @mut T {
@@ -177,6 +316,12 @@ impl<T: 'static + Send + Shrink> Shrink for @mut T {
             L.push_map((**self).shrink(), |u| @mut u);
         }
     }
+
+    fn shrink_deep(&self) -> Lazy<@mut T> {
+        do Lazy::create |L| {
+            L.push_map((**self).shrink_deep(), |u| @mut u);
+        }
+    }
 }
 
 impl Shrink for ~str {
@@ -188,6 +333,13 @@ impl Shrink for ~str {
             }
         }
     }
+
+    fn shrink_deep(&self) -> Lazy<~str> {
+        do Lazy::create |L| {
+            let v = self.iter().collect::<~[char]>();
+            L.push_map(v.shrink_deep(), |v| std::str::from_chars(v));
+        }
+    }
 }
 
 impl<T: Send + Clone + Shrink> Shrink for ~[T] {
@@ -227,8 +379,78 @@ impl<T: Send + Clone + Shrink> Shrink for ~[T] {
         }
         L
     }
+
+    /// Remove runs of adjacent elements, the longest first
+    fn shrink_deep(&self) -> Lazy<~[T]> {
+        let mut L = Lazy::new();
+        if self.len() > 2 {
+            push_run_removals(&mut L, self.clone(), self.len() - 1, 0);
+        }
+        L
+    }
 }
 
+/// Lazily push `v` with each run of `k` adjacent elements from `i` on removed,
+/// and then with shorter runs, down to two elements
+fn push_run_removals<T: Send + Clone>(L: &mut Lazy<~[T]>, v: ~[T], k: uint, i: uint) {
+    do L.push_thunk((v, k, i)) |L, (v, k, i)| {
+        if i + k <= v.len() {
+            let mut v1 = v.slice(0, i).to_owned();
+            v1.push_all(v.slice(i + k, v.len()));
+            L.push(v1);
+            push_run_removals(L, v, k, i + 1);
+        } else if k > 2 {
+            push_run_removals(L, v, k - 1, 0);
+        }
+    }
+}
+
+/// Shrink the elements of `v` one at a time, keeping its length
+pub fn shrink_elements<T: Send + Clone + Shrink>(v: ~[T]) -> Lazy<~[T]> {
+    let mut L = Lazy::new();
+    push_element_shrinks(&mut L, v, 0);
+    L
+}
+
+fn push_element_shrinks<T: Send + Clone + Shrink>(L: &mut Lazy<~[T]>, v: ~[T], index: uint) {
+    do L.push_thunk((index, v)) |L, (index, v)| {
+        if index < v.len() {
+            do L.push_map_env(v[index].shrink(), (index, v.clone()))
+                    |selt, &(ref index, ref v)| {
+                let mut v1 = v.clone();
+                v1[*index] = selt;
+                v1
+            }
+            push_element_shrinks(L, v, index + 1);
+        }
+    }
+}
+
+/// Fixed-size arrays shrink their elements, never their length
+macro_rules! shrink_array( ($N:expr, $($i:expr),+) => (
+        impl<T: Send + Clone + Shrink> Shrink for [T, ..$N] {
+            fn shrink(&self) -> Lazy<[T, ..$N]> {
+                do Lazy::create |L| {
+                    let v = self.iter().map(|x| x.clone()).collect::<~[T]>();
+                    L.push_map(shrink_elements(v), |v| [$(v[$i].clone()),+]);
+                }
+            }
+        }
+    )
+)
+
+shrink_array!(1, 0)
+shrink_array!(2, 0, 1)
+shrink_array!(3, 0, 1, 2)
+shrink_array!(4, 0, 1, 2, 3)
+shrink_array!(5, 0, 1, 2, 3, 4)
+shrink_array!(6, 0, 1, 2, 3, 4, 5)
+shrink_array!(7, 0, 1, 2, 3, 4, 5, 6)
+shrink_array!(8, 0, 1, 2, 3, 4, 5, 6, 7)
+shrink_array!(16, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15)
+shrink_array!(32, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+              16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31)
+
 
 impl<T: Send + Clone + Shrink> Shrink for Cell<T> {
     fn shrink(&self) -> Lazy<Cell<T>> {
@@ -253,3 +475,108 @@ impl<K: Eq + Hash + Clone + Shrink + Send,
         }
     }
 }
+
+impl<K: Eq + Hash + Clone + Shrink + Send> Shrink for HashSet<K> {
+    fn shrink(&self) -> Lazy<HashSet<K>> {
+        do Lazy::create |L| {
+            if self.len() > 0 {
+                let v = self.iter().map(|x| x.clone()).collect::<~[K]>();
+                L.push_map(v.shrink(), |v| v.move_iter().collect());
+            }
+        }
+    }
+}
+
+impl<K: TotalOrd + Clone + Shrink + Send,
+     V: Clone + Shrink + Send> Shrink for TreeMap<K, V> {
+    fn shrink(&self) -> Lazy<TreeMap<K, V>> {
+        do Lazy::create |L| {
+            if self.len() > 0 {
+                let v = self.iter().map(|(k, x)| (k.clone(), x.clone())).collect::<~[(K, V)]>();
+                L.push_map(v.shrink(), |v| v.move_iter().collect());
+            }
+        }
+    }
+}
+
+impl<T: TotalOrd + Clone + Shrink + Send> Shrink for TreeSet<T> {
+    fn shrink(&self) -> Lazy<TreeSet<T>> {
+        do Lazy::create |L| {
+            if self.len() > 0 {
+                let v = self.iter().map(|x| x.clone()).collect::<~[T]>();
+                L.push_map(v.shrink(), |v| v.move_iter().collect());
+            }
+        }
+    }
+}
+
+impl<T: Clone + Shrink + Send> Shrink for RingBuf<T> {
+    fn shrink(&self) -> Lazy<RingBuf<T>> {
+        do Lazy::create |L| {
+            if self.len() > 0 {
+                let v = self.iter().map(|x| x.clone()).collect::<~[T]>();
+                L.push_map(v.shrink(), |v| v.move_iter().collect());
+            }
+        }
+    }
+}
+
+impl<T: Clone + Shrink + Send> Shrink for DList<T> {
+    fn shrink(&self) -> Lazy<DList<T>> {
+        do Lazy::create |L| {
+            if self.len() > 0 {
+                let v = self.iter().map(|x| x.clone()).collect::<~[T]>();
+                L.push_map(v.shrink(), |v| v.move_iter().collect());
+            }
+        }
+    }
+}
+
+impl<T: Ord + Clone + Shrink + Send> Shrink for PriorityQueue<T> {
+    fn shrink(&self) -> Lazy<PriorityQueue<T>> {
+        do Lazy::create |L| {
+            if self.len() > 0 {
+                let v = self.iter().map(|x| x.clone()).collect::<~[T]>();
+                L.push_map(v.shrink(), |v| PriorityQueue::from_vec(v));
+            }
+        }
+    }
+}
+
+/// Remove bits like `~[bool]`, then clear set bits one at a time
+impl Shrink for Bitv {
+    fn shrink(&self) -> Lazy<Bitv> {
+        do Lazy::create |L| {
+            let v = self.to_bools();
+            if v.len() > 0 {
+                L.push_map(v.shrink(), |v| from_bools(v));
+                do L.push_thunk(v) |L, v| {
+                    for i in range(0, v.len()) {
+                        if v[i] {
+                            let mut w = v.clone();
+                            w[i] = false;
+                            L.push(from_bools(w));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<V: Clone + Shrink + Send> Shrink for SmallIntMap<V> {
+    fn shrink(&self) -> Lazy<SmallIntMap<V>> {
+        do Lazy::create |L| {
+            if self.len() > 0 {
+                let v = self.iter().map(|(k, x)| (k, x.clone())).collect::<~[(uint, V)]>();
+                do L.push_map(v.shrink()) |v| {
+                    let mut m = SmallIntMap::new();
+                    for (k, x) in v.move_iter() {
+                        m.insert(k, x);
+                    }
+                    m
+                }
+            }
+        }
+    }
+}
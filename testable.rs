@@ -0,0 +1,77 @@
+// vim: sts=4 sw=4 et
+
+/**
+ The result of testing a property on one value.
+ */
+#[deriving(Eq, Clone)]
+pub enum TestResult {
+    /// The property holds
+    Pass,
+    /// The property is falsified, for the given reason
+    Fail(~str),
+    /// The value does not satisfy the property's preconditions
+    Discard,
+}
+
+impl TestResult {
+    /// Return true if the result is `Fail`
+    pub fn is_failure(&self) -> bool {
+        match *self {
+            Fail(*) => true,
+            _ => false,
+        }
+    }
+
+    /// Return the failure reason, or the empty string if not a failure
+    pub fn reason(&self) -> ~str {
+        match *self {
+            Fail(ref s) => s.clone(),
+            _ => ~"",
+        }
+    }
+}
+
+/**
+ The Testable trait is implemented by the return types of properties.
+ */
+pub trait Testable {
+    fn result(self) -> TestResult;
+}
+
+impl Testable for TestResult {
+    #[inline]
+    fn result(self) -> TestResult { self }
+}
+
+impl Testable for bool {
+    fn result(self) -> TestResult {
+        if self { Pass } else { Fail(~"property is false") }
+    }
+}
+
+/// A property returning `()` passes unless it fails the task
+impl Testable for () {
+    #[inline]
+    fn result(self) -> TestResult { Pass }
+}
+
+/// The error value is reported as the failure reason
+impl<E: ToStr> Testable for Result<(), E> {
+    fn result(self) -> TestResult {
+        match self {
+            Ok(()) => Pass,
+            Err(e) => Fail(e.to_str()),
+        }
+    }
+}
+
+/**
+ Test `prop` only if `cond` holds, otherwise discard the value.
+
+ Example:
+
+ `quick_check!(|v: ~[u8]| implies(v.len() >= 2, || v[0] <= v[1] || v[0] > v[1]));`
+ */
+pub fn implies<R: Testable>(cond: bool, prop: &fn() -> R) -> TestResult {
+    if cond { prop().result() } else { Discard }
+}
@@ -0,0 +1,113 @@
+// vim: sts=4 sw=4 et
+
+/*!
+ Arbitrary and Shrink for tuples.
+
+ Both impls are generated from the same list, so that every tuple that can be
+ generated can also be shrunk. Shrink for pairs, the most common tuples, is
+ typed out instead.
+ */
+
+use lazy::Lazy;
+use shrink::Shrink;
+use arbitrary::{Arbitrary, Source, arbitrary};
+
+/// Implement Arbitrary for the tuple with types `T`
+macro_rules! arbitrary_tuple(
+    ($($T:ident),+) => (
+        impl<$($T: Arbitrary),+> Arbitrary for ($($T),+) {
+            fn arbitrary(g: &mut Source) -> ($($T),+) {
+                ($(arbitrary::<$T>(g)),+)
+            }
+        }
+    )
+)
+
+/**
+ Implement Arbitrary and Shrink for the tuple with types `T` and matching
+ lower case names `x`, listed again as a tuple `xs`.
+
+ Each element is shrunk in turn, keeping the others, with both `shrink` and
+ `shrink_deep`.
+ */
+macro_rules! tuple_impls(
+    ($($T:ident $x:ident),+ ; $xs:tt) => (
+        arbitrary_tuple!($($T),+)
+
+        impl<$($T: Send + Clone + Shrink),+> Shrink for ($($T),+) {
+            #[allow(unused_variable)]
+            fn shrink(&self) -> Lazy<($($T),+)> {
+                do Lazy::create |L| {
+                    match self {
+                        &($(ref $x),+) => {
+                            $(
+                                do L.push_map_env($x.shrink(), self.clone()) |s, t| {
+                                    let $xs = t.clone();
+                                    let $x = s;
+                                    $xs
+                                }
+                            )+
+                        }
+                    }
+                }
+            }
+
+            #[allow(unused_variable)]
+            fn shrink_deep(&self) -> Lazy<($($T),+)> {
+                do Lazy::create |L| {
+                    match self {
+                        &($(ref $x),+) => {
+                            $(
+                                do L.push_map_env($x.shrink_deep(), self.clone()) |s, t| {
+                                    let $xs = t.clone();
+                                    let $x = s;
+                                    $xs
+                                }
+                            )+
+                        }
+                    }
+                }
+            }
+        }
+    )
+)
+
+arbitrary_tuple!(A, B)
+
+/* type out the (A, B) tuple case as we can save half the .clone() calls */
+impl<A: Send + Clone + Shrink, B: Send + Clone + Shrink> Shrink for (A, B) {
+    fn shrink(&self) -> Lazy<(A, B)> {
+        match self {
+            &(ref a, ref b) => {
+                let mut L = Lazy::new();
+                L.push_map_env(a.shrink(), b.clone(), |s, b| (s, b.clone()));
+                L.push_map_env(b.shrink(), a.clone(), |s, a| (a.clone(), s));
+                L
+            }
+        }
+    }
+
+    fn shrink_deep(&self) -> Lazy<(A, B)> {
+        match self {
+            &(ref a, ref b) => {
+                let mut L = Lazy::new();
+                L.push_map_env(a.shrink_deep(), b.clone(), |s, b| (s, b.clone()));
+                L.push_map_env(b.shrink_deep(), a.clone(), |s, a| (a.clone(), s));
+                L
+            }
+        }
+    }
+}
+
+tuple_impls!(A a, B b, C c; (a, b, c))
+tuple_impls!(A a, B b, C c, D d; (a, b, c, d))
+tuple_impls!(A a, B b, C c, D d, E e; (a, b, c, d, e))
+tuple_impls!(A a, B b, C c, D d, E e, F f; (a, b, c, d, e, f))
+tuple_impls!(A a, B b, C c, D d, E e, F f, G g; (a, b, c, d, e, f, g))
+tuple_impls!(A a, B b, C c, D d, E e, F f, G g, H h; (a, b, c, d, e, f, g, h))
+tuple_impls!(A a, B b, C c, D d, E e, F f, G g, H h, I i; (a, b, c, d, e, f, g, h, i))
+tuple_impls!(A a, B b, C c, D d, E e, F f, G g, H h, I i, J j; (a, b, c, d, e, f, g, h, i, j))
+tuple_impls!(A a, B b, C c, D d, E e, F f, G g, H h, I i, J j, K k;
+            (a, b, c, d, e, f, g, h, i, j, k))
+tuple_impls!(A a, B b, C c, D d, E e, F f, G g, H h, I i, J j, K k, L l;
+            (a, b, c, d, e, f, g, h, i, j, k, l))